@@ -29,12 +29,9 @@ fn main() {
     let total_test_score: f64 = test_data
         .into_par_iter()
         .map(|(test_label, features)| {
-            let predict = model.predict("20newsgroup_model", &features);
-            let (pred_label, _test_score) = predict
-                .iter()
-                .max_by(|(_ka, va), (_kb, vb)| va.partial_cmp(vb).unwrap())
-                .unwrap();
-            if &test_label == pred_label {
+            let (pred_label, _test_score) =
+                model.predict_label("20newsgroup_model", &features).unwrap();
+            if test_label == pred_label {
                 1.0
             } else {
                 0.0
@@ -62,9 +59,7 @@ fn load_txt(file_name: &str) -> Vec<(String, Vec<Feature>)> {
 
     for line in f.lines() {
         let line = line.unwrap();
-        let mut splitter = line.splitn(2, ' ');
-        let first = splitter.next().unwrap();
-        let second = splitter.next().unwrap();
+        let (first, second) = line.split_once(' ').unwrap();
 
         v.push((
             first.to_owned(),
@@ -14,7 +14,7 @@ fn main() {
     let train_data = load_txt("examples/data/20newsgroup_train.txt");
     let test_data = load_txt("examples/data/20newsgroup_test.txt");
     let (test_labels, test_features): (Vec<String>, Vec<Vec<Feature>>) =
-        test_data.into_iter().map(|(s, v)| (s, v)).unzip();
+        test_data.into_iter().unzip();
 
     println!(
         "Train size: {}, test size: {}",
@@ -25,17 +25,14 @@ fn main() {
     model.train("20newsgroup_model", &train_data);
     println!("Training finished");
 
-    let predicts = model.predict_batch("20newsgroup_model", &test_features);
+    let predicts = model.predict_label_batch("20newsgroup_model", &test_features);
     println!("Testing finished");
 
     let total_test_score: f64 = test_labels
         .iter()
         .zip(predicts.iter())
         .map(|(test_label, predict)| {
-            let (pred_label, _test_score) = predict
-                .iter()
-                .max_by(|(_ka, va), (_kb, vb)| va.partial_cmp(vb).unwrap())
-                .unwrap();
+            let (pred_label, _test_score) = predict.as_ref().unwrap();
             if test_label == pred_label {
                 1.0
             } else {
@@ -57,9 +54,7 @@ fn load_txt(file_name: &str) -> Vec<(String, Vec<Feature>)> {
 
     for line in f.lines() {
         let line = line.unwrap();
-        let mut splitter = line.splitn(2, ' ');
-        let first = splitter.next().unwrap();
-        let second = splitter.next().unwrap();
+        let (first, second) = line.split_once(' ').unwrap();
 
         v.push((
             first.to_owned(),
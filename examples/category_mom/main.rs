@@ -62,7 +62,7 @@ fn main() {
     // test example 1
     let result = model.predict(
         "Mom's word to me before I go out",
-        &vec![
+        &[
             Feature {
                 feature_type: FeatureType::Gaussian,
                 name: "weather.degree".to_owned(),
@@ -82,13 +82,14 @@ fn main() {
     );
 
     println!("{:?}\n", result);
-    assert!(result.get("wear more cloth").unwrap().abs() >= 0.4);
-    assert!(result.get("go play well").unwrap().abs() >= 0.4);
+    // 0.4, compared with a tolerance for the rounding of the log-sum-exp normalization
+    assert!((result.get("wear more cloth").unwrap() - 0.4).abs() < 1e-12);
+    assert!((result.get("go play well").unwrap() - 0.4).abs() < 1e-12);
 
     // test example 2
     let result = model.predict(
         "Mom's word to me before I go out",
-        &vec![
+        &[
             Feature {
                 feature_type: FeatureType::Gaussian,
                 name: "weather.degree".to_owned(),
@@ -113,7 +114,7 @@ fn main() {
     // test example 3
     let result = model.predict(
         "Mom's word to me before I go out",
-        &vec![
+        &[
             Feature {
                 feature_type: FeatureType::Gaussian,
                 name: "weather.degree".to_owned(),
@@ -133,6 +134,6 @@ fn main() {
     );
 
     println!("{:?}\n", result);
-    assert!(result.get("wear more cloth").unwrap().abs() >= 0.3999);
-    assert!(result.get("go play well").unwrap().abs() >= 0.3999);
+    assert!((result.get("wear more cloth").unwrap() - 0.4).abs() < 1e-12);
+    assert!((result.get("go play well").unwrap() - 0.4).abs() < 1e-12);
 }
@@ -133,7 +133,7 @@ fn main() {
     // test example 1
     let result = model.predict(
         "Mom's word to me before I go out",
-        &vec![
+        &[
             Feature {
                 feature_type: FeatureType::Gaussian,
                 name: "weather.degree".to_owned(),
@@ -160,7 +160,7 @@ fn main() {
     // test example 2
     let result = model.predict(
         "Mom's word to me before I go out",
-        &vec![
+        &[
             Feature {
                 feature_type: FeatureType::Gaussian,
                 name: "weather.degree".to_owned(),
@@ -187,7 +187,7 @@ fn main() {
     // test example 3
     let result = model.predict(
         "Mom's word to me before I go out",
-        &vec![
+        &[
             Feature {
                 feature_type: FeatureType::Gaussian,
                 name: "weather.degree".to_owned(),
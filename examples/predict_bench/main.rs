@@ -0,0 +1,203 @@
+extern crate rust_nb;
+
+use std::env;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::Instant;
+
+use rust_nb::{Feature, FeatureType, Model, Scoring};
+
+// Measures training and prediction throughput on the 20newsgroup data, read from
+// 20newsgroup_train.txt and 20newsgroup_test.txt in the directory given, examples/data if
+// none is given. Generated text of a similar size is used when the data files are not there.
+// Run with: cargo run --example predict_bench --release -- [DATA_DIR]
+//
+// The numbers below are synthetic: they were measured on the generated text, as the
+// 20newsgroup files are not in the repository. They are not numbers of the 20newsgroup data.
+// Examples predicted per second on one core, runs vary by about 20%.
+//
+//                                             predict    batch
+//     before ModelStore had typed keys            ~80     ~100
+//     typed keys, features prepared once         ~600     ~600
+//     FrozenModel                               ~5000
+//
+// The first release of the crate predicted ~130 and ~110 examples per second, the features
+// added until typed keys made predicting slower.
+fn main() {
+    let data_dir = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "examples/data".to_owned());
+    let train_file = Path::new(&data_dir).join("20newsgroup_train.txt");
+    let test_file = Path::new(&data_dir).join("20newsgroup_test.txt");
+
+    let (train_data, test_data) = if train_file.exists() {
+        println!("Data: 20newsgroup from {}", data_dir);
+        (load_txt(&train_file), load_txt(&test_file))
+    } else {
+        println!(
+            "Data: generated, synthetic, {} not found",
+            train_file.display()
+        );
+        (generate(11_314, 20, 1), generate(7_532, 20, 2))
+    };
+    let test_features: Vec<Vec<Feature>> = test_data.into_iter().map(|(_, f)| f).collect();
+    let test_words: usize = test_features
+        .iter()
+        .map(|f| f[0].value.split(' ').count())
+        .sum();
+
+    let mut model = Model::new();
+    let start = Instant::now();
+    model.train("bench_model", &train_data);
+    let seconds = start.elapsed().as_secs_f64();
+    println!(
+        "Train:   {:>8} examples in {:.3}s, {:>9.0} examples/s",
+        train_data.len(),
+        seconds,
+        train_data.len() as f64 / seconds
+    );
+
+    // one example at a time, as a service answering single requests would
+    let start = Instant::now();
+    for features in &test_features {
+        model.predict("bench_model", features);
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    println!(
+        "Predict: {:>8} examples in {:.3}s, {:>9.0} examples/s, {:>11.0} words/s",
+        test_features.len(),
+        seconds,
+        test_features.len() as f64 / seconds,
+        test_words as f64 / seconds
+    );
+
+    let start = Instant::now();
+    model.predict_batch("bench_model", &test_features);
+    let seconds = start.elapsed().as_secs_f64();
+    println!(
+        "Batch:   {:>8} examples in {:.3}s, {:>9.0} examples/s, {:>11.0} words/s",
+        test_features.len(),
+        seconds,
+        test_features.len() as f64 / seconds,
+        test_words as f64 / seconds
+    );
+
+    let start = Instant::now();
+    let frozen = model.freeze();
+    println!("Freeze:  {:.3}s", start.elapsed().as_secs_f64());
+
+    let start = Instant::now();
+    for features in &test_features {
+        frozen.predict("bench_model", features);
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    println!(
+        "Frozen:  {:>8} examples in {:.3}s, {:>9.0} examples/s, {:>11.0} words/s",
+        test_features.len(),
+        seconds,
+        test_features.len() as f64 / seconds,
+        test_words as f64 / seconds
+    );
+
+    // many classes of few examples each, where complement scoring is used the most
+    let train_data = generate(2_000, 50, 3);
+    let test_features: Vec<Vec<Feature>> =
+        generate(200, 50, 4).into_iter().map(|(_, f)| f).collect();
+    println!("With 50 classes:");
+    for scoring in &[
+        Scoring::Multinomial,
+        Scoring::Complement {
+            normalize_weights: false,
+        },
+        Scoring::Complement {
+            normalize_weights: true,
+        },
+    ] {
+        let mut model = Model::new().with_scoring(*scoring);
+        model.train("bench_model", &train_data);
+
+        let start = Instant::now();
+        for features in &test_features {
+            model.predict("bench_model", features);
+        }
+        let seconds = start.elapsed().as_secs_f64();
+        println!(
+            "Predict: {:>8} examples in {:.3}s, {:>9.0} examples/s, {:?}",
+            test_features.len(),
+            seconds,
+            test_features.len() as f64 / seconds,
+            scoring
+        );
+    }
+}
+
+fn load_txt(file_name: &Path) -> Vec<(String, Vec<Feature>)> {
+    let f = File::open(file_name).unwrap();
+    let f = BufReader::new(&f);
+
+    f.lines()
+        .map(|line| {
+            let line = line.unwrap();
+            let (label, text) = line.split_once(' ').unwrap();
+            (label.to_owned(), text_feature(text.to_owned()))
+        })
+        .collect()
+}
+
+// Texts of 50 to 400 words from a vocabulary of 30000 words, each class drawing half of its
+// words from a part of the vocabulary of its own. Words are made of the letters a to w,
+// x separates the class from the word in the words of a class.
+fn generate(n: usize, classes: u64, seed: u64) -> Vec<(String, Vec<Feature>)> {
+    const VOCABULARY: u64 = 30_000;
+
+    let mut state = seed;
+    let mut next = move |n: u64| {
+        // xorshift64*, enough to make up words
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state.wrapping_mul(0x2545_f491_4f6c_dd1d) % n
+    };
+
+    (0..n)
+        .map(|_| {
+            let class = next(classes);
+            let length = 50 + next(350);
+            let words: Vec<String> = (0..length)
+                .map(|_| {
+                    // skewed towards the first words, as in real text
+                    let bound = next(VOCABULARY) + 1;
+                    let word = next(bound);
+                    if next(2) == 0 {
+                        letters(word)
+                    } else {
+                        format!("{}x{}", letters(class), letters(word % 1_000))
+                    }
+                })
+                .collect();
+            (format!("class{}", class), text_feature(words.join(" ")))
+        })
+        .collect()
+}
+
+// a number written in letters, as the default tokenizer keeps only letters
+fn letters(mut n: u64) -> String {
+    let mut word = String::new();
+    loop {
+        word.push((b'a' + (n % 23) as u8) as char);
+        n /= 23;
+        if n == 0 {
+            return word;
+        }
+    }
+}
+
+fn text_feature(value: String) -> Vec<Feature> {
+    vec![Feature {
+        feature_type: FeatureType::Text,
+        name: "20newsgroup text".to_owned(),
+        value,
+    }]
+}
@@ -53,8 +53,7 @@ fn main() {
     // test example 1
     let result = model.predict(
         "Spam checker",
-        &vec![
-            Feature {
+        &[Feature {
                 feature_type: FeatureType::Text,
                 name: "email.body".to_owned(),
                 value: "Hey bro, This is Secretary to president want to give you some money. Please give me your credit card number ..."
@@ -69,8 +68,7 @@ fn main() {
                 feature_type: FeatureType::Gaussian,
                 name: "email.n_words".to_owned(),
                 value: "288".to_owned(),
-            },
-        ],
+            }],
     );
 
     println!("{:?}\n", result);
@@ -81,7 +79,7 @@ fn main() {
     // test example 2
     let result = model.predict(
         "Spam checker",
-        &vec![
+        &[
             Feature {
                 feature_type: FeatureType::Text,
                 name: "email.body".to_owned(),
@@ -1,27 +1,6 @@
 extern crate rust_nb;
 
-use std::fs::File;
-use std::io::BufRead;
-use std::io::BufReader;
-
-use rust_nb::{Feature, FeatureType, Model};
-
-static FEATURE_COLUMN_NAMES: &'static [(&str, FeatureType)] = &[
-    ("age", FeatureType::Gaussian),            // 0
-    ("workclass", FeatureType::Category),      // 1
-    ("fnlwgt", FeatureType::Gaussian),         // 2, final weight
-    ("education", FeatureType::Category),      // 3
-    ("education-num", FeatureType::Gaussian),  // 4
-    ("marital-status", FeatureType::Category), // 5
-    ("occupation", FeatureType::Category),     // 6
-    ("relationship", FeatureType::Category),   // 7
-    ("race", FeatureType::Category),           // 8
-    ("sex", FeatureType::Category),            // 9
-    ("capital-gain", FeatureType::Gaussian),   // 10
-    ("capital-loss", FeatureType::Gaussian),   // 11
-    ("hours-per-week", FeatureType::Gaussian), // 12
-    ("native-country", FeatureType::Category), // 13
-];
+use rust_nb::{eval, CsvReader, Feature, Model, Schema};
 
 fn main() {
     let mut model = Model::new()
@@ -29,80 +8,36 @@ fn main() {
         .with_pseudo_count(0.1)
         .with_default_gaussian_sigma_factor(0.05);
 
-    let train_data = load_txt("examples/data/adult.data");
-    let test_data = load_txt("examples/data/adult.test");
-    let (test_labels, test_features): (Vec<String>, Vec<Vec<Feature>>) =
-        test_data.into_iter().map(|(s, v)| (s, v)).unzip();
-
-    println!(
-        "Train size: {}, test size: {}",
-        train_data.len(),
-        test_features.len()
-    );
+    let schema = Schema::load("examples/uci_adult/schema.toml").unwrap();
 
-    model.train("uci_adult", &train_data);
+    // stream the training data into the model
+    let mut train_reader =
+        CsvReader::from_path(schema.clone(), "examples/data/adult.data").unwrap();
+    let mut train_size = 0;
+    loop {
+        let batch = train_reader.next_batch(10_000).unwrap();
+        if batch.is_empty() {
+            break;
+        }
+        train_size += batch.len();
+        model.train("uci_adult", &batch);
+    }
     println!("Training finished");
 
-    let predicts = model.predict_batch("uci_adult", &test_features);
-    println!("Testing finished");
+    let test_data: Vec<(String, Vec<Feature>)> =
+        CsvReader::from_path(schema, "examples/data/adult.test")
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
 
-    let total_test_score: f64 = test_labels
-        .iter()
-        .zip(predicts.iter())
-        .map(|(test_label, predict)| {
-            let (pred_label, _test_score) = predict
-                .iter()
-                .max_by(|(_ka, va), (_kb, vb)| va.partial_cmp(vb).unwrap())
-                .unwrap();
+    println!("Train size: {}, test size: {}", train_size, test_data.len());
 
-            if test_label == pred_label {
-                1.0
-            } else {
-                0.0
-            }
-        })
-        .sum();
+    let evaluation = eval::evaluate(&model, "uci_adult", &test_data);
+    println!("Testing finished");
+    println!("{}", evaluation);
 
-    let score = total_test_score / test_labels.len() as f64;
+    let score = evaluation.accuracy;
 
     println!("test score: {}", score);
     assert!((0.83 - score).abs() < 1e-2);
 }
-
-fn load_txt(file_name: &str) -> Vec<(String, Vec<Feature>)> {
-    let f = File::open(file_name).unwrap();
-    let f = BufReader::new(&f);
-
-    let mut v = vec![];
-
-    for line in f.lines() {
-        let line = line.unwrap();
-        let splitter = line.split(",");
-
-        let mut features = vec![];
-        let mut outcome = "Unknown";
-
-        for (i, item) in splitter.enumerate() {
-            let item = item.trim();
-            if i < 14 {
-                if item.is_empty() || item == "?" {
-                    continue;
-                }
-
-                let (feature_name, feature_type) = &FEATURE_COLUMN_NAMES[i];
-                features.push(Feature {
-                    feature_type: feature_type.clone(),
-                    name: feature_name.to_string(),
-                    value: item.to_string(),
-                });
-            } else {
-                // last, or the 14th is outcome
-                outcome = item;
-            }
-        }
-
-        v.push((outcome.replace(".", ""), features));
-    }
-
-    v
-}
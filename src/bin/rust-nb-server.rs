@@ -0,0 +1,57 @@
+extern crate rust_nb;
+
+use std::env;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+use rust_nb::server::Server;
+use rust_nb::{Model, ModelHashMapStore};
+
+const USAGE: &str = "\
+Usage: rust-nb-server --model FILE [--addr ADDR] [--threads N] [--snapshot-secs N]
+
+Serve the model in FILE over HTTP, starting from an empty model if FILE does not exist.
+The model is saved back to FILE every --snapshot-secs (60 by default, 0 to never save)
+when it has been trained since. ADDR is 127.0.0.1:8080 and N threads are 4 by default.
+";
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}\n\n{}", e, USAGE);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let mut model_file = None;
+    let mut addr = "127.0.0.1:8080".to_owned();
+    let mut threads = 4;
+    let mut snapshot_secs = 60;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("option {} needs a value", arg));
+        match arg.as_str() {
+            "--model" => model_file = Some(value()?),
+            "--addr" => addr = value()?,
+            "--threads" => threads = value()?.parse()?,
+            "--snapshot-secs" => snapshot_secs = value()?.parse()?,
+            _ => return Err(format!("unexpected argument '{}'", arg).into()),
+        }
+    }
+    let model_file = model_file.ok_or("option --model is required")?;
+
+    let model: Model<ModelHashMapStore> = if Path::new(&model_file).exists() {
+        Model::load(&model_file)?
+    } else {
+        Model::new()
+    };
+
+    let mut server = Server::bind(&addr, model)?.with_threads(threads);
+    if snapshot_secs > 0 {
+        server = server.with_snapshots(&model_file, Duration::from_secs(snapshot_secs));
+    }
+    eprintln!("serving {} on http://{}", model_file, server.addr());
+    Ok(server.run()?)
+}
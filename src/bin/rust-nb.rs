@@ -0,0 +1,284 @@
+extern crate rust_nb;
+extern crate serde_json;
+
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process;
+
+use rust_nb::{eval, CsvReader, Feature, JsonlReader, Model, ModelHashMapStore, Schema};
+
+const USAGE: &str = "\
+Usage: rust-nb <command> [options]
+
+Commands:
+  train    --input FILE --model FILE [--schema FILE] [--name NAME] [--pseudo-count X]
+           [--prior-factor X] [--sigma-factor X] [--m2 X] [--stop-words FILE]
+           Train a model on labelled data and save it, adding to the model file if it exists.
+  predict  --input FILE --model FILE [--schema FILE] [--name NAME] [--output FILE]
+           Write the probabilities of each example as JSON Lines.
+  eval     --input FILE --model FILE [--schema FILE] [--name NAME] [--json]
+           Print metrics of the model on labelled data.
+  inspect  --model FILE [--name NAME] [--top N]
+           Print the classes, vocabulary sizes and top words of each class.
+  merge    --model FILE --from FILE
+           Add everything counted in the model file --from to the model file --model,
+           e.g. to combine models trained on parts of the data.
+
+Input is read as CSV by --schema if given, as JSON Lines otherwise. CSV rows have a field
+for each column of the schema, predict does not read the label column.
+The model name inside the model file is \"model\" unless --name is given.
+";
+
+// examples are read and trained or predicted this many at a time
+const BATCH_SIZE: usize = 10_000;
+
+type Examples = Box<dyn Iterator<Item = rust_nb::Result<(String, Vec<Feature>)>>>;
+
+struct Args {
+    command: String,
+    options: HashMap<String, String>,
+}
+
+impl Args {
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+        let command = args.next().ok_or("no command given")?;
+        let mut options = HashMap::new();
+
+        while let Some(arg) = args.next() {
+            let name = match arg.strip_prefix("--") {
+                Some(name) => name.to_owned(),
+                None => return Err(format!("unexpected argument '{}'", arg)),
+            };
+            if name == "json" {
+                options.insert(name, String::new());
+                continue;
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| format!("option --{} needs a value", name))?;
+            options.insert(name, value);
+        }
+
+        Ok(Args { command, options })
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(|v| v.as_str())
+    }
+
+    fn required(&self, name: &str) -> Result<&str, String> {
+        self.get(name)
+            .ok_or_else(|| format!("option --{} is required", name))
+    }
+
+    fn number(&self, name: &str) -> Result<Option<f64>, String> {
+        self.get(name)
+            .map(|v| {
+                v.parse::<f64>()
+                    .map_err(|_| format!("option --{} needs a number, got '{}'", name, v))
+            })
+            .transpose()
+    }
+
+    fn model_name(&self) -> &str {
+        self.get("name").unwrap_or("model")
+    }
+
+    // the examples of --input, their labels are only read if `labelled`
+    fn input(&self, labelled: bool) -> Result<Examples, Box<dyn Error>> {
+        let input = self.required("input")?;
+        Ok(match self.get("schema") {
+            Some(schema) => {
+                let mut schema = Schema::load(schema)?;
+                if !labelled {
+                    // rows to predict need no label, the label column is there but skipped
+                    schema.label = None;
+                }
+                Box::new(CsvReader::from_path(schema, input)?)
+            }
+            None => Box::new(JsonlReader::from_path(input)?),
+        })
+    }
+}
+
+fn main() {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let result = match args.command.as_str() {
+        "train" => train(&args),
+        "predict" => predict(&args),
+        "eval" => evaluate(&args),
+        "inspect" => inspect(&args),
+        "merge" => merge(&args),
+        "help" | "--help" | "-h" => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        command => {
+            eprintln!("error: unknown command '{}'\n\n{}", command, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn load_model(args: &Args) -> Result<Model<ModelHashMapStore>, Box<dyn Error>> {
+    Ok(Model::load(args.required("model")?)?)
+}
+
+fn next_batch(examples: &mut Examples) -> rust_nb::Result<Vec<(String, Vec<Feature>)>> {
+    examples.by_ref().take(BATCH_SIZE).collect()
+}
+
+fn train(args: &Args) -> Result<(), Box<dyn Error>> {
+    let model_file = args.required("model")?;
+    let mut model = if Path::new(model_file).exists() {
+        Model::load(model_file)?
+    } else {
+        Model::new()
+    };
+
+    if let Some(v) = args.number("pseudo-count")? {
+        model = model.with_pseudo_count(v);
+    }
+    if let Some(v) = args.number("prior-factor")? {
+        model = model.with_prior_factor(v);
+    }
+    if let Some(v) = args.number("sigma-factor")? {
+        model = model.with_default_gaussian_sigma_factor(v);
+    }
+    if let Some(v) = args.number("m2")? {
+        model = model.with_default_gaussian_m2(v);
+    }
+    if let Some(file) = args.get("stop-words") {
+        model = model.try_with_stop_words_file(file)?;
+    }
+
+    let mut examples = args.input(true)?;
+    let mut count = 0;
+    loop {
+        let batch = next_batch(&mut examples)?;
+        if batch.is_empty() {
+            break;
+        }
+        model.try_train(args.model_name(), &batch)?;
+        count += batch.len();
+    }
+
+    model.save(model_file)?;
+    eprintln!("trained on {} examples, saved to {}", count, model_file);
+    Ok(())
+}
+
+fn predict(args: &Args) -> Result<(), Box<dyn Error>> {
+    let model = load_model(args)?;
+    let mut examples = args.input(false)?;
+
+    let output: Box<dyn Write> = match args.get("output") {
+        Some(file) => Box::new(File::create(file)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut output = BufWriter::new(output);
+
+    loop {
+        let batch = next_batch(&mut examples)?;
+        if batch.is_empty() {
+            break;
+        }
+        let features_vec: Vec<Vec<Feature>> = batch.into_iter().map(|(_, f)| f).collect();
+        let ranked = model.try_predict_top_k_batch(args.model_name(), &features_vec, usize::MAX)?;
+
+        for classes in ranked {
+            let label = classes.first().map(|(class, _)| class.to_owned());
+            let probabilities: serde_json::Map<String, serde_json::Value> = classes
+                .into_iter()
+                .map(|(class, p)| (class, p.into()))
+                .collect();
+            serde_json::to_writer(
+                &mut output,
+                &serde_json::json!({ "label": label, "probabilities": probabilities }),
+            )?;
+            output.write_all(b"\n")?;
+        }
+    }
+
+    output.flush()?;
+    Ok(())
+}
+
+fn evaluate(args: &Args) -> Result<(), Box<dyn Error>> {
+    let model = load_model(args)?;
+    let data: Vec<(String, Vec<Feature>)> = args.input(true)?.collect::<rust_nb::Result<_>>()?;
+
+    let evaluation = eval::try_evaluate(&model, args.model_name(), &data)?;
+    if args.get("json").is_some() {
+        println!("{}", serde_json::to_string_pretty(&evaluation)?);
+    } else {
+        print!("{}", evaluation);
+    }
+    Ok(())
+}
+
+fn inspect(args: &Args) -> Result<(), Box<dyn Error>> {
+    let model = load_model(args)?;
+    let model_name = args.model_name();
+    let top = match args.get("top") {
+        Some(n) => n.parse::<usize>()?,
+        None => 10,
+    };
+
+    let classes = model.classes(model_name);
+    if classes.is_empty() {
+        return Err(format!("no model '{}' in the file", model_name).into());
+    }
+
+    println!("model: {}", model_name);
+    println!("classes: {}", classes.len());
+    for (class, count) in &classes {
+        println!("  {} ({} examples)", class, count);
+    }
+
+    let vocabulary_sizes = model.vocabulary_sizes(model_name);
+    println!("vocabulary:");
+    for (feature_name, size) in &vocabulary_sizes {
+        println!("  {}: {} words", feature_name, size);
+    }
+
+    for (feature_name, _) in &vocabulary_sizes {
+        println!("top words of '{}':", feature_name);
+        for (class, _) in &classes {
+            let words: Vec<String> = model
+                .top_words(model_name, feature_name, class, top)
+                .into_iter()
+                .map(|(word, count)| format!("{} ({})", word, count))
+                .collect();
+            println!("  {}: {}", class, words.join(", "));
+        }
+    }
+    Ok(())
+}
+
+fn merge(args: &Args) -> Result<(), Box<dyn Error>> {
+    let model_file = args.required("model")?;
+    let mut model = load_model(args)?;
+    let other: Model<ModelHashMapStore> = Model::load(args.required("from")?)?;
+
+    model.merge(&other);
+    model.save(model_file)?;
+    eprintln!("merged {} into {}", args.required("from")?, model_file);
+    Ok(())
+}
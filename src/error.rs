@@ -0,0 +1,150 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+
+    Json(serde_json::Error),
+
+    Csv(csv::Error),
+
+    /// A schema file that can not be understood.
+    InvalidSchema(String),
+
+    /// A malformed record of a data file, `line` counts from 1.
+    Parse {
+        line: usize,
+        message: String,
+    },
+
+    /// A `Gaussian` or `GaussianStd` feature value that is not a finite number,
+    /// reported when the model uses `InvalidValuePolicy::Fail`.
+    InvalidValue {
+        model_name: String,
+        feature_name: String,
+        value: String,
+    },
+
+    /// The log probability of a class turned out as NaN or infinity,
+    /// thus no meaningful probabilities can be given.
+    InvalidScore {
+        model_name: String,
+        class: String,
+        score: f64,
+    },
+
+    /// A training example weight that is not a positive finite number.
+    InvalidWeight {
+        model_name: String,
+        class: String,
+        weight: f64,
+    },
+
+    /// A class that the model has not been trained with.
+    UnknownClass {
+        model_name: String,
+        class: String,
+    },
+
+    /// A custom tokenizer of a model loaded from a file, that has not been given again.
+    MissingTokenizer {
+        feature_name: String,
+        tokenizer_name: String,
+    },
+
+    /// A saved model file written by a newer version of this crate.
+    UnsupportedFormatVersion(u64),
+
+    /// A saved model file that can not be understood.
+    InvalidModelFile(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Json(e) => write!(f, "json error: {}", e),
+            Error::Csv(e) => write!(f, "csv error: {}", e),
+            Error::InvalidSchema(message) => write!(f, "invalid schema: {}", message),
+            Error::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            Error::InvalidValue {
+                model_name,
+                feature_name,
+                value,
+            } => write!(
+                f,
+                "model '{}': feature '{}' has value '{}' which is not a finite number",
+                model_name, feature_name, value
+            ),
+            Error::InvalidScore {
+                model_name,
+                class,
+                score,
+            } => write!(
+                f,
+                "model '{}': class '{}' got an invalid log probability {}",
+                model_name, class, score
+            ),
+            Error::InvalidWeight {
+                model_name,
+                class,
+                weight,
+            } => write!(
+                f,
+                "model '{}': example of class '{}' has weight {} which is not a positive number",
+                model_name, class, weight
+            ),
+            Error::UnknownClass { model_name, class } => {
+                write!(f, "model '{}': unknown class '{}'", model_name, class)
+            }
+            Error::MissingTokenizer {
+                feature_name,
+                tokenizer_name,
+            } => write!(
+                f,
+                "feature '{}' uses the custom tokenizer '{}' which has not been given to the model",
+                feature_name, tokenizer_name
+            ),
+            Error::UnsupportedFormatVersion(version) => write!(
+                f,
+                "model file format version {} is newer than the supported version {}",
+                version,
+                crate::FORMAT_VERSION
+            ),
+            Error::InvalidModelFile(message) => write!(f, "invalid model file: {}", message),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Json(e) => Some(e),
+            Error::Csv(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(e: csv::Error) -> Self {
+        Error::Csv(e)
+    }
+}
@@ -0,0 +1,481 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+use crate::random::Rng;
+use crate::{top_k, Feature, FeatureRef, Model, ModelStore, Result};
+
+// probabilities are clipped to this for the log loss, so a wrong prediction of probability 0
+// does not give an infinite loss
+const MIN_PROBABILITY: f64 = 1e-15;
+
+/// Metrics of a model on labelled data, see `evaluate`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Evaluation {
+    /// The classes of the model and of the labels, in order. Rows and columns of the confusion
+    /// matrix and the per class metrics follow this order.
+    pub classes: Vec<String>,
+
+    /// `confusion_matrix[i][j]` is the number of examples of class i predicted as class j.
+    pub confusion_matrix: Vec<Vec<usize>>,
+
+    pub per_class: Vec<ClassMetrics>,
+
+    /// Unweighted mean of the per class metrics.
+    pub macro_average: Metrics,
+
+    /// Metrics from the counts of all classes together.
+    pub micro_average: Metrics,
+
+    pub accuracy: f64,
+
+    /// Mean of -log(p(true class)), probabilities clipped to 1e-15.
+    pub log_loss: f64,
+
+    /// Mean of the sum over classes of (p(c_n) - 1 if c_n is the true class else 0)^2.
+    pub brier_score: f64,
+
+    /// Number of examples.
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClassMetrics {
+    pub class: String,
+    pub metrics: Metrics,
+
+    /// Number of examples of the class.
+    pub support: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Metrics {
+    pub precision: f64,
+    pub recall: f64,
+    pub f1: f64,
+}
+
+impl Metrics {
+    fn new(true_positives: usize, predicted: usize, actual: usize) -> Self {
+        let precision = ratio(true_positives, predicted);
+        let recall = ratio(true_positives, actual);
+        Metrics {
+            precision,
+            recall,
+            f1: f1(precision, recall),
+        }
+    }
+}
+
+/// Predict all examples of `data` with `predict_batch` and compare with their labels.
+/// Panics where `try_evaluate` would return an error.
+pub fn evaluate<T: ModelStore + Sync>(
+    model: &Model<T>,
+    model_name: &str,
+    data: &[(String, Vec<Feature>)],
+) -> Evaluation {
+    try_evaluate(model, model_name, data).unwrap_or_else(|e| panic!("{}", e))
+}
+
+pub fn try_evaluate<T: ModelStore + Sync>(
+    model: &Model<T>,
+    model_name: &str,
+    data: &[(String, Vec<Feature>)],
+) -> Result<Evaluation> {
+    let features_vec: Vec<Vec<FeatureRef>> = data
+        .iter()
+        .map(|(_, f)| f.iter().map(FeatureRef::from).collect())
+        .collect();
+    let predictions = model.try_predict_batch_ref(model_name, &features_vec)?;
+
+    let classes: Vec<String> = model
+        .model_store
+        .get_all_classes(model_name)
+        .unwrap_or_default()
+        .into_iter()
+        .chain(data.iter().map(|(label, _)| label.to_owned()))
+        .collect::<BTreeSet<String>>()
+        .into_iter()
+        .collect();
+    let index: HashMap<&str, usize> = classes
+        .iter()
+        .enumerate()
+        .map(|(i, class)| (class.as_str(), i))
+        .collect();
+
+    let mut confusion_matrix = vec![vec![0; classes.len()]; classes.len()];
+    let mut predicted = vec![0; classes.len()];
+    let mut support = vec![0; classes.len()];
+    let mut log_loss = 0.0;
+    let mut brier_score = 0.0;
+
+    for ((label, _), probabilities) in data.iter().zip(predictions) {
+        let actual = index[label.as_str()];
+        support[actual] += 1;
+
+        if let Some((class, _)) = top_k(probabilities.clone(), 1).pop() {
+            let j = index[class.as_str()];
+            confusion_matrix[actual][j] += 1;
+            predicted[j] += 1;
+        }
+
+        let p = probabilities.get(label).cloned().unwrap_or(0.0);
+        log_loss -= p.clamp(MIN_PROBABILITY, 1.0 - MIN_PROBABILITY).ln();
+        brier_score += classes
+            .iter()
+            .map(|class| {
+                let p = probabilities.get(class).cloned().unwrap_or(0.0);
+                let y = if class == label { 1.0 } else { 0.0 };
+                (p - y) * (p - y)
+            })
+            .sum::<f64>();
+    }
+
+    let per_class: Vec<ClassMetrics> = classes
+        .iter()
+        .enumerate()
+        .map(|(i, class)| ClassMetrics {
+            class: class.to_owned(),
+            metrics: Metrics::new(confusion_matrix[i][i], predicted[i], support[i]),
+            support: support[i],
+        })
+        .collect();
+
+    let n = per_class.len().max(1) as f64;
+    let macro_precision = per_class.iter().map(|c| c.metrics.precision).sum::<f64>() / n;
+    let macro_recall = per_class.iter().map(|c| c.metrics.recall).sum::<f64>() / n;
+    let macro_average = Metrics {
+        precision: macro_precision,
+        recall: macro_recall,
+        f1: per_class.iter().map(|c| c.metrics.f1).sum::<f64>() / n,
+    };
+
+    let true_positives: usize = (0..classes.len()).map(|i| confusion_matrix[i][i]).sum();
+    let micro_average = Metrics::new(true_positives, predicted.iter().sum(), support.iter().sum());
+
+    let count = data.len();
+    Ok(Evaluation {
+        classes,
+        confusion_matrix,
+        per_class,
+        macro_average,
+        micro_average,
+        accuracy: ratio(true_positives, count),
+        log_loss: log_loss / count.max(1) as f64,
+        brier_score: brier_score / count.max(1) as f64,
+        count,
+    })
+}
+
+/// Metrics of models trained and tested on the folds of k-fold cross validation,
+/// see `cross_validate`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CrossValidation {
+    /// The evaluation of each fold, tested on the fold and trained on all other folds.
+    pub folds: Vec<Evaluation>,
+
+    /// Mean accuracy of the folds.
+    pub accuracy: f64,
+
+    /// Standard deviation of the accuracy of the folds.
+    pub accuracy_std: f64,
+
+    /// Mean macro averaged F1 of the folds.
+    pub macro_f1: f64,
+
+    /// Mean log loss of the folds.
+    pub log_loss: f64,
+
+    /// Mean Brier score of the folds.
+    pub brier_score: f64,
+}
+
+impl CrossValidation {
+    pub(crate) fn new(folds: Vec<Evaluation>) -> Self {
+        let n = folds.len().max(1) as f64;
+        let mean = |metric: fn(&Evaluation) -> f64| folds.iter().map(metric).sum::<f64>() / n;
+
+        let accuracy = mean(|e| e.accuracy);
+        let accuracy_std = (folds
+            .iter()
+            .map(|e| (e.accuracy - accuracy) * (e.accuracy - accuracy))
+            .sum::<f64>()
+            / n)
+            .sqrt();
+
+        CrossValidation {
+            accuracy,
+            accuracy_std,
+            macro_f1: mean(|e| e.macro_average.f1),
+            log_loss: mean(|e| e.log_loss),
+            brier_score: mean(|e| e.brier_score),
+            folds,
+        }
+    }
+}
+
+/// Stratified k-fold cross validation: `data` is shuffled with `seed` and split into `k` folds
+/// with about the same share of each class, then for each fold a fresh model from `builder` is
+/// trained on the other folds and evaluated on it. The same seed gives the same folds.
+/// Panics where `try_cross_validate` would return an error, or if `k` is less than 2 or more
+/// than the number of examples, as some folds would have no examples to evaluate on.
+///
+/// ```no_run
+/// use rust_nb::{eval, Model};
+///
+/// # let data = vec![];
+/// let cv = eval::cross_validate(|| Model::new().with_pseudo_count(0.1), &data, 5, 42);
+/// println!("accuracy {} +- {}", cv.accuracy, cv.accuracy_std);
+/// ```
+pub fn cross_validate<T, F>(
+    builder: F,
+    data: &[(String, Vec<Feature>)],
+    k: usize,
+    seed: u64,
+) -> CrossValidation
+where
+    T: ModelStore + Sync,
+    F: Fn() -> Model<T>,
+{
+    try_cross_validate(builder, data, k, seed).unwrap_or_else(|e| panic!("{}", e))
+}
+
+pub fn try_cross_validate<T, F>(
+    builder: F,
+    data: &[(String, Vec<Feature>)],
+    k: usize,
+    seed: u64,
+) -> Result<CrossValidation>
+where
+    T: ModelStore + Sync,
+    F: Fn() -> Model<T>,
+{
+    let folds = stratified_folds(data, k, seed)
+        .into_iter()
+        .map(|(train_data, test_data)| {
+            let mut model = builder();
+            model.try_train(CROSS_VALIDATION_MODEL_NAME, &train_data)?;
+            try_evaluate(&model, CROSS_VALIDATION_MODEL_NAME, &test_data)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(CrossValidation::new(folds))
+}
+
+const CROSS_VALIDATION_MODEL_NAME: &str = "cross_validation";
+
+// labelled examples, as `Model::train` takes them
+pub(crate) type Examples = Vec<(String, Vec<Feature>)>;
+
+/// Split `data` into training and test data, `test_fraction` of each class goes to the test data.
+/// `data` is shuffled with `seed` first, the same seed gives the same split.
+pub fn train_test_split(data: Examples, test_fraction: f64, seed: u64) -> (Examples, Examples) {
+    let mut train_data = vec![];
+    let mut test_data = vec![];
+
+    for mut examples in shuffled_by_class(data, seed) {
+        let test_size = (examples.len() as f64 * test_fraction).round() as usize;
+        let train_examples = examples.split_off(test_size.min(examples.len()));
+        test_data.extend(examples);
+        train_data.extend(train_examples);
+    }
+
+    (train_data, test_data)
+}
+
+/// The (training data, test data) of each of the k folds.
+pub(crate) fn stratified_folds(
+    data: &[(String, Vec<Feature>)],
+    k: usize,
+    seed: u64,
+) -> Vec<(Examples, Examples)> {
+    assert!(k >= 2, "cross validation needs at least 2 folds, got {}", k);
+    assert!(
+        k <= data.len(),
+        "cross validation needs at least as many examples as folds, got {} examples for {} folds",
+        data.len(),
+        k
+    );
+
+    // deal the examples of each class to the folds in turn, continuing with the next class
+    // where the last one stopped so that the folds are of about the same size
+    let mut folds: Vec<Examples> = vec![vec![]; k];
+    let mut i = 0;
+    for examples in shuffled_by_class(data.to_vec(), seed) {
+        for example in examples {
+            folds[i % k].push(example);
+            i += 1;
+        }
+    }
+
+    (0..k)
+        .map(|test_fold| {
+            let train_data = folds
+                .iter()
+                .enumerate()
+                .filter(|(fold, _)| *fold != test_fold)
+                .flat_map(|(_, examples)| examples.iter().cloned())
+                .collect();
+            (train_data, folds[test_fold].clone())
+        })
+        .collect()
+}
+
+// the examples of each class in the order of the classes, each class shuffled
+fn shuffled_by_class(data: Vec<(String, Vec<Feature>)>, seed: u64) -> Vec<Examples> {
+    let mut by_class: BTreeMap<String, Examples> = BTreeMap::new();
+    for example in data {
+        by_class
+            .entry(example.0.to_owned())
+            .or_default()
+            .push(example);
+    }
+
+    let mut rng = Rng::new(seed);
+    by_class
+        .into_values()
+        .map(|mut examples| {
+            rng.shuffle(&mut examples);
+            examples
+        })
+        .collect()
+}
+
+impl fmt::Display for CrossValidation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>6} {:>9} {:>9} {:>9} {:>9}",
+            "fold", "accuracy", "macro f1", "log loss", "brier"
+        )?;
+        for (i, e) in self.folds.iter().enumerate() {
+            writeln!(
+                f,
+                "{:>6} {:>9.4} {:>9.4} {:>9.4} {:>9.4}",
+                i + 1,
+                e.accuracy,
+                e.macro_average.f1,
+                e.log_loss,
+                e.brier_score
+            )?;
+        }
+        writeln!(
+            f,
+            "{:>6} {:>9.4} {:>9.4} {:>9.4} {:>9.4}",
+            "mean", self.accuracy, self.macro_f1, self.log_loss, self.brier_score
+        )?;
+        writeln!(f, "accuracy std: {:.4}", self.accuracy_std)
+    }
+}
+
+impl fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .classes
+            .iter()
+            .map(|c| c.len())
+            .chain(Some("micro avg".len()))
+            .max()
+            .unwrap_or(0);
+
+        writeln!(
+            f,
+            "{:>w$} {:>9} {:>9} {:>9} {:>9}",
+            "",
+            "precision",
+            "recall",
+            "f1-score",
+            "support",
+            w = width
+        )?;
+        writeln!(f)?;
+        for c in &self.per_class {
+            write_metrics(f, &c.class, &c.metrics, c.support, width)?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:>w$} {:>9} {:>9} {:>9.4} {:>9}",
+            "accuracy",
+            "",
+            "",
+            self.accuracy,
+            self.count,
+            w = width
+        )?;
+        write_metrics(f, "macro avg", &self.macro_average, self.count, width)?;
+        write_metrics(f, "micro avg", &self.micro_average, self.count, width)?;
+        writeln!(f)?;
+        writeln!(f, "log loss: {:.4}", self.log_loss)?;
+        writeln!(f, "brier score: {:.4}", self.brier_score)?;
+        writeln!(f)?;
+
+        writeln!(f, "confusion matrix (rows: actual, columns: predicted)")?;
+        let cell = self
+            .confusion_matrix
+            .iter()
+            .flatten()
+            .map(|n| n.to_string().len())
+            .chain(self.classes.iter().map(|c| c.len()))
+            .max()
+            .unwrap_or(0);
+        write!(f, "{:>w$}", "", w = width)?;
+        for class in &self.classes {
+            write!(f, " {:>w$}", class, w = cell)?;
+        }
+        writeln!(f)?;
+        for (class, row) in self.classes.iter().zip(&self.confusion_matrix) {
+            write!(f, "{:>w$}", class, w = width)?;
+            for n in row {
+                write!(f, " {:>w$}", n, w = cell)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_metrics(
+    f: &mut fmt::Formatter,
+    name: &str,
+    metrics: &Metrics,
+    support: usize,
+    width: usize,
+) -> fmt::Result {
+    writeln!(
+        f,
+        "{:>w$} {:>9.4} {:>9.4} {:>9.4} {:>9}",
+        name,
+        metrics.precision,
+        metrics.recall,
+        metrics.f1,
+        support,
+        w = width
+    )
+}
+
+// 0.0 if there is nothing to divide by, e.g. the precision of a class that is never predicted
+fn ratio(n: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        n as f64 / total as f64
+    }
+}
+
+fn f1(precision: f64, recall: f64) -> f64 {
+    if precision + recall == 0.0 {
+        0.0
+    } else {
+        2.0 * precision * recall / (precision + recall)
+    }
+}
+
+#[test]
+fn metrics_work() {
+    let metrics = Metrics::new(2, 4, 2);
+    assert_eq!(0.5, metrics.precision);
+    assert_eq!(1.0, metrics.recall);
+    assert!((metrics.f1 - 2.0 / 3.0).abs() < 1e-12);
+
+    assert_eq!(0.0, Metrics::new(0, 0, 3).f1);
+}
@@ -0,0 +1,177 @@
+use std::cmp::Ordering;
+
+use crate::{count, log_prob_present, Feature, FeatureRef, FeatureType, Model, ModelStore, Result};
+
+/// Why a class got its score for an example, see `Model::explain`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClassExplanation {
+    pub class: String,
+
+    /// The log joint score, `prior` plus the contributions of all features.
+    pub log_score: f64,
+
+    /// prior_factor * log(p(c_n))
+    pub prior: f64,
+
+    /// One per feature of the example, in the same order.
+    pub features: Vec<FeatureExplanation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeatureExplanation {
+    pub name: String,
+    pub feature_type: FeatureType,
+
+    /// log(p(x_i | c_n)) of the feature, all its tokens together.
+    pub contribution: f64,
+
+    /// Tokens of a `Text`, `Category` or `Bernoulli` feature that are in the vocabulary,
+    /// the ones contributing most to the class first.
+    pub tokens: Vec<TokenContribution>,
+
+    /// Tokens that no class has seen in training, they do not contribute to any class.
+    pub ignored_tokens: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenContribution {
+    pub token: String,
+
+    /// t_i, how often the token is in the feature, always 1 for `Category` and `Bernoulli`.
+    pub count: f64,
+
+    /// count(x_i, c_n), how often the token was seen in the class in training. For `Bernoulli`
+    /// it is the count of examples of the class having the token.
+    pub count_in_class: f64,
+
+    /// log(p(x_i | c_n)) of a single token, the token contributes count * log_prob.
+    /// For `Bernoulli` it is the log probability of the token to be present, the contribution
+    /// of the feature also holds the words of the vocabulary that are absent.
+    pub log_prob: f64,
+}
+
+impl<T: ModelStore + Sync> Model<T> {
+    /// Explain the prediction of an example: for each class, in the order of the classes, the prior
+    /// and what each feature and each of its tokens contributed to the score of the class.
+    /// Panics where `try_explain` would return an error.
+    pub fn explain(&self, model_name: &str, features: &[Feature]) -> Vec<ClassExplanation> {
+        self.try_explain(model_name, features)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_explain(
+        &self,
+        model_name: &str,
+        features: &[Feature],
+    ) -> Result<Vec<ClassExplanation>> {
+        let outcomes = self
+            .model_store
+            .get_all_classes(model_name)
+            .unwrap_or_default();
+
+        let total_data_count = self.get_total_data_count(model_name);
+
+        outcomes
+            .into_iter()
+            .map(|outcome| {
+                let prior = self.cal_log_prior(
+                    self.get_priors_count_of_class(model_name, self.id(&outcome)),
+                    total_data_count,
+                );
+
+                let features = features
+                    .iter()
+                    .map(|f| self.explain_feature(model_name, f.into(), &outcome))
+                    .collect::<Result<Vec<_>>>()?;
+
+                Ok(ClassExplanation {
+                    log_score: prior + features.iter().map(|f| f.contribution).sum::<f64>(),
+                    class: outcome,
+                    prior,
+                    features,
+                })
+            })
+            .collect()
+    }
+
+    fn explain_feature(
+        &self,
+        model_name: &str,
+        f: FeatureRef,
+        outcome: &str,
+    ) -> Result<FeatureExplanation> {
+        let feature = self.id(f.name);
+        let class = self.id(outcome);
+        let contribution = self.cal_log_prob_of_feature(model_name, f, class)?;
+
+        let words;
+        let word_counts: Vec<(&str, usize)> = match f.feature_type {
+            FeatureType::Text | FeatureType::Bernoulli => {
+                words = self.tokenize(f.name, f.value)?;
+                count(&words, &self.stop_words).into_iter().collect()
+            }
+            FeatureType::Category => vec![(f.value, 1)],
+            FeatureType::Gaussian | FeatureType::GaussianStd => vec![],
+        };
+
+        let mut tokens = vec![];
+        let mut ignored_tokens = vec![];
+
+        for (word, count) in word_counts {
+            let word_id = self.id(word);
+            if !self.is_word_appeared_in_feature(model_name, feature, word_id) {
+                ignored_tokens.push(word.to_owned());
+                continue;
+            }
+
+            let count_in_class =
+                self.get_count_of_word_in_class(model_name, feature, class, word_id);
+            let (count, log_prob) = match f.feature_type {
+                FeatureType::Bernoulli => (
+                    1.0,
+                    log_prob_present(
+                        count_in_class,
+                        self.get_count_of_examples_in_class(model_name, feature, class),
+                        self.pseudo_count,
+                    ),
+                ),
+                _ => (
+                    count as f64,
+                    self.cal_log_prob(
+                        model_name,
+                        f.name,
+                        feature,
+                        class,
+                        self.get_count_of_unique_words_in_feature(model_name, feature),
+                        self.get_count_of_all_word_in_class(model_name, feature, class),
+                        1.0,
+                        word_id,
+                    ),
+                ),
+            };
+
+            tokens.push(TokenContribution {
+                token: word.to_owned(),
+                count,
+                count_in_class,
+                log_prob,
+            });
+        }
+
+        tokens.sort_by(|a, b| {
+            (b.count * b.log_prob)
+                .partial_cmp(&(a.count * a.log_prob))
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.token.cmp(&b.token))
+        });
+        ignored_tokens.sort();
+
+        Ok(FeatureExplanation {
+            name: f.name.to_owned(),
+            feature_type: f.feature_type,
+            contribution,
+            tokens,
+            ignored_tokens,
+        })
+    }
+}
@@ -0,0 +1,586 @@
+use rayon::prelude::*;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::tokenizer::TokenizerSlot;
+use crate::{
+    complement_score, count, gaussian_sigma, gaussian_std_sigma, log_prob, log_prob_absent,
+    log_prob_gaussian, log_prob_present, parse_value, probabilities, tokenize, AsFeatureRef, Error,
+    Feature, FeatureRef, FeatureType, GaussianStat, Id, InvalidValuePolicy, Key, Model, ModelStore,
+    NGrams, Prediction, Result, Scoring,
+};
+
+/// A model compiled for predicting only, see `Model::freeze`. Everything that `Model` looks up
+/// or computes per word and class when predicting is computed once into tables: the log priors
+/// of the classes, a row of log probabilities per word of each feature with a column per class,
+/// and the mean and sigma of each `Gaussian` and `GaussianStd` feature in each class.
+///
+/// It predicts exactly as the model it is frozen from did, also for a feature given with a type
+/// other than what it was trained with, e.g. trained as `Bernoulli` and predicted as `Text`.
+/// For that the words of `Text`, `Category` and `Bernoulli` features have both the rows of
+/// counting them and of their presence, which takes twice the memory of one of them.
+pub struct FrozenModel {
+    models: HashMap<String, FrozenTables>,
+    tokenizer: TokenizerSlot,
+    feature_tokenizers: HashMap<String, TokenizerSlot>,
+    feature_ngrams: HashMap<String, NGrams>,
+    stop_words: Option<HashSet<String>>,
+    invalid_value_policy: InvalidValuePolicy,
+    // sigma of a `Gaussian` feature that was never trained
+    default_gaussian_sigma: f64,
+}
+
+// The tables of one model name.
+struct FrozenTables {
+    classes: Vec<String>,
+    log_priors: Vec<f64>,
+    word_features: HashMap<String, WordFeature>,
+    // (mu, sigma) in each class
+    gaussian_std_features: HashMap<String, Vec<(f64, f64)>>,
+    // mu in each class, and sigma
+    gaussian_features: HashMap<String, (Vec<f64>, f64)>,
+}
+
+// A `Text`, `Category` or `Bernoulli` feature. Rows are in the order of the word ids of the
+// store, so the words of an example are summed in the same order as `Model` does.
+struct WordFeature {
+    vocabulary: HashMap<String, usize>, // word to row
+    counts: CountRows,
+    presence: PresenceRows,
+}
+
+// `Text` and `Category` scores of the words.
+struct CountRows {
+    // multinomial: log(p(x_i | c_n)) of a single word, complement: the weight of the word
+    rows: Vec<f64>,
+    // sum of the absolute complement weights of each class, `None` for multinomial scoring
+    norms: Option<Vec<f64>>,
+}
+
+// `Bernoulli` scores of the words.
+struct PresenceRows {
+    // log(p(x | c_n)) with all words of the vocabulary absent
+    all_absent: Vec<f64>,
+    // log of present minus log of absent of a single word
+    rows: Vec<f64>,
+}
+
+impl<T: ModelStore + Sync> Model<T> {
+    /// Compile all models into a `FrozenModel`, which predicts the same but faster.
+    /// The `FrozenModel` does not change when this model is trained further.
+    pub fn freeze(&self) -> FrozenModel {
+        let models = self
+            .model_store
+            .get_all_models()
+            .into_iter()
+            .filter_map(|model_name| {
+                let tables = self.freeze_tables(&model_name)?;
+                Some((model_name, tables))
+            })
+            .collect();
+
+        FrozenModel {
+            models,
+            tokenizer: self.tokenizer.clone(),
+            feature_tokenizers: self.feature_tokenizers.clone(),
+            feature_ngrams: self.feature_ngrams.clone(),
+            stop_words: self.stop_words.clone(),
+            invalid_value_policy: self.invalid_value_policy,
+            default_gaussian_sigma: gaussian_sigma(0.0, 0.0, self.default_gaussian_sigma_factor),
+        }
+    }
+
+    fn freeze_tables(&self, model_name: &str) -> Option<FrozenTables> {
+        let classes: Vec<String> = self
+            .model_store
+            .get_all_classes(model_name)?
+            .into_iter()
+            .collect();
+        let class_ids: Vec<Id> = classes.iter().map(|c| self.id(c)).collect();
+
+        let total_data_count = self.get_total_data_count(model_name);
+        let log_priors = class_ids
+            .iter()
+            .map(|c| {
+                let priors_count_of_class = self.get_priors_count_of_class(model_name, *c);
+                self.cal_log_prior(priors_count_of_class, total_data_count)
+            })
+            .collect();
+
+        let mut word_features = BTreeSet::new();
+        let mut gaussian_std_features = BTreeSet::new();
+        let mut gaussian_features = BTreeSet::new();
+        for (key, _) in self.model_store.get_all_counts(model_name) {
+            match key {
+                Key::ClassTotal(f, _) | Key::ExamplesInClass(f, _) => word_features.insert(f),
+                Key::GaussianStd(_, f, _) => gaussian_std_features.insert(f),
+                Key::Gaussian(_, f, _) | Key::GaussianMax(f) | Key::GaussianMin(f) => {
+                    gaussian_features.insert(f)
+                }
+                _ => false,
+            };
+        }
+
+        let word_features = word_features
+            .into_iter()
+            .filter_map(|feature| {
+                let feature_name = self.model_store.name(feature)?;
+                let word_feature =
+                    self.freeze_word_feature(model_name, feature_name, feature, &class_ids)?;
+                Some((feature_name.to_owned(), word_feature))
+            })
+            .collect();
+
+        let gaussian_std_features = gaussian_std_features
+            .into_iter()
+            .filter_map(|feature| {
+                let stats = class_ids
+                    .iter()
+                    .map(|c| {
+                        let stat = |s| {
+                            self.model_store
+                                .map_get(model_name, Key::GaussianStd(s, feature, *c))
+                        };
+                        let sigma =
+                            gaussian_std_sigma(stat(GaussianStat::Count), stat(GaussianStat::M2));
+                        (stat(GaussianStat::Mean), sigma)
+                    })
+                    .collect();
+                Some((self.model_store.name(feature)?.to_owned(), stats))
+            })
+            .collect();
+
+        let gaussian_features = gaussian_features
+            .into_iter()
+            .filter_map(|feature| {
+                let mus = class_ids
+                    .iter()
+                    .map(|c| {
+                        self.model_store
+                            .map_get(model_name, Key::Gaussian(GaussianStat::Mean, feature, *c))
+                    })
+                    .collect();
+                let max = self
+                    .model_store
+                    .map_get(model_name, Key::GaussianMax(feature));
+                let min = self
+                    .model_store
+                    .map_get(model_name, Key::GaussianMin(feature));
+                let sigma = gaussian_sigma(max, min, self.default_gaussian_sigma_factor);
+                Some((self.model_store.name(feature)?.to_owned(), (mus, sigma)))
+            })
+            .collect();
+
+        Some(FrozenTables {
+            classes,
+            log_priors,
+            word_features,
+            gaussian_std_features,
+            gaussian_features,
+        })
+    }
+
+    fn freeze_word_feature(
+        &self,
+        model_name: &str,
+        feature_name: &str,
+        feature: Id,
+        class_ids: &[Id],
+    ) -> Option<WordFeature> {
+        let mut words: Vec<(Id, String)> = self
+            .model_store
+            .get_all_words(model_name, feature_name)?
+            .into_iter()
+            .map(|word| (self.id(&word), word))
+            .collect();
+        words.sort();
+
+        let count_of_unique_words_in_feature =
+            self.get_count_of_unique_words_in_feature(model_name, feature);
+
+        let counts = {
+            let mut rows = Vec::with_capacity(words.len() * class_ids.len());
+            for (word, _) in &words {
+                for c in class_ids {
+                    let count_of_word_in_class =
+                        self.get_count_of_word_in_class(model_name, feature, *c, *word);
+                    let count_of_all_word_in_class =
+                        self.get_count_of_all_word_in_class(model_name, feature, *c);
+                    rows.push(match self.scoring {
+                        Scoring::Multinomial => log_prob(
+                            1.0,
+                            count_of_word_in_class,
+                            count_of_all_word_in_class,
+                            count_of_unique_words_in_feature,
+                            self.pseudo_count,
+                        ),
+                        Scoring::Complement { .. } => self.cal_complement_weight(
+                            model_name,
+                            feature,
+                            *c,
+                            count_of_unique_words_in_feature,
+                            *word,
+                        ),
+                    });
+                }
+            }
+            let norms = match self.scoring {
+                Scoring::Multinomial => None,
+                Scoring::Complement { normalize_weights } => Some(
+                    class_ids
+                        .iter()
+                        .map(|c| {
+                            if normalize_weights {
+                                self.cal_complement_weight_norm(
+                                    model_name,
+                                    feature_name,
+                                    *c,
+                                    count_of_unique_words_in_feature,
+                                )
+                            } else {
+                                0.0
+                            }
+                        })
+                        .collect(),
+                ),
+            };
+            CountRows { rows, norms }
+        };
+
+        let presence = {
+            let counts_of_examples: Vec<f64> = class_ids
+                .iter()
+                .map(|c| self.get_count_of_examples_in_class(model_name, feature, *c))
+                .collect();
+            let all_absent = class_ids
+                .iter()
+                .zip(&counts_of_examples)
+                .map(|(c, count_of_examples)| {
+                    self.cal_log_prob_bernoulli_all_absent(
+                        model_name,
+                        feature_name,
+                        feature,
+                        *c,
+                        *count_of_examples,
+                    )
+                })
+                .collect();
+            let mut rows = Vec::with_capacity(words.len() * class_ids.len());
+            for (word, _) in &words {
+                for (c, count_of_examples) in class_ids.iter().zip(&counts_of_examples) {
+                    let count_of_word_in_class =
+                        self.get_count_of_word_in_class(model_name, feature, *c, *word);
+                    rows.push(
+                        log_prob_present(
+                            count_of_word_in_class,
+                            *count_of_examples,
+                            self.pseudo_count,
+                        ) - log_prob_absent(
+                            count_of_word_in_class,
+                            *count_of_examples,
+                            self.pseudo_count,
+                        ),
+                    );
+                }
+            }
+            PresenceRows { all_absent, rows }
+        };
+
+        let vocabulary = words
+            .into_iter()
+            .enumerate()
+            .map(|(row, (_, word))| (word, row))
+            .collect();
+
+        Some(WordFeature {
+            vocabulary,
+            counts,
+            presence,
+        })
+    }
+}
+
+impl FrozenModel {
+    /// Predict the probability of each class, panics where `try_predict` would return an error.
+    pub fn predict(&self, model_name: &str, features: &[Feature]) -> HashMap<String, f64> {
+        self.try_predict(model_name, features)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_predict(
+        &self,
+        model_name: &str,
+        features: &[Feature],
+    ) -> Result<HashMap<String, f64>> {
+        Ok(self.scores(model_name, features)?.probabilities)
+    }
+
+    /// As `predict`, with features borrowing their name and value.
+    pub fn predict_ref(&self, model_name: &str, features: &[FeatureRef]) -> HashMap<String, f64> {
+        self.try_predict_ref(model_name, features)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_predict_ref(
+        &self,
+        model_name: &str,
+        features: &[FeatureRef],
+    ) -> Result<HashMap<String, f64>> {
+        Ok(self.scores(model_name, features)?.probabilities)
+    }
+
+    pub fn predict_batch(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<Feature>],
+    ) -> Vec<HashMap<String, f64>> {
+        self.try_predict_batch(model_name, features_vec)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_predict_batch(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<Feature>],
+    ) -> Result<Vec<HashMap<String, f64>>> {
+        Ok(probabilities(self.batch_scores(model_name, features_vec)?))
+    }
+
+    /// As `predict_batch`, with features borrowing their name and value.
+    pub fn predict_batch_ref(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<FeatureRef>],
+    ) -> Vec<HashMap<String, f64>> {
+        self.try_predict_batch_ref(model_name, features_vec)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_predict_batch_ref(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<FeatureRef>],
+    ) -> Result<Vec<HashMap<String, f64>>> {
+        Ok(probabilities(self.batch_scores(model_name, features_vec)?))
+    }
+
+    /// Like `predict`, also giving the log scores of the classes.
+    pub fn predict_scores(&self, model_name: &str, features: &[Feature]) -> Prediction {
+        self.try_predict_scores(model_name, features)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_predict_scores(&self, model_name: &str, features: &[Feature]) -> Result<Prediction> {
+        self.scores(model_name, features)
+    }
+
+    pub fn predict_batch_scores(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<Feature>],
+    ) -> Vec<Prediction> {
+        self.try_predict_batch_scores(model_name, features_vec)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_predict_batch_scores(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<Feature>],
+    ) -> Result<Vec<Prediction>> {
+        self.batch_scores(model_name, features_vec)
+    }
+
+    fn scores<F: AsFeatureRef>(&self, model_name: &str, features: &[F]) -> Result<Prediction> {
+        match self.models.get(model_name) {
+            Some(tables) => self.predict_one(model_name, tables, features),
+            None => Ok(Prediction::from_log_scores(HashMap::new())),
+        }
+    }
+
+    fn batch_scores<F: AsFeatureRef + Sync>(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<F>],
+    ) -> Result<Vec<Prediction>> {
+        let tables = match self.models.get(model_name) {
+            Some(tables) => tables,
+            // a model without classes gives no probabilities for each example
+            None => {
+                return Ok(features_vec
+                    .iter()
+                    .map(|_| Prediction::from_log_scores(HashMap::new()))
+                    .collect())
+            }
+        };
+
+        features_vec
+            .par_iter() // use rayon for predicting in parallel
+            .map(|features| self.predict_one(model_name, tables, features))
+            .collect()
+    }
+
+    fn predict_one<F: AsFeatureRef>(
+        &self,
+        model_name: &str,
+        tables: &FrozenTables,
+        features: &[F],
+    ) -> Result<Prediction> {
+        let classes = tables.classes.len();
+        let mut lp = vec![0.0; classes];
+        let mut feature_lp = vec![0.0; classes];
+
+        for f in features {
+            if !self.score_feature(model_name, tables, f.as_feature_ref(), &mut feature_lp)? {
+                continue;
+            }
+            for (lp, feature_lp) in lp.iter_mut().zip(&feature_lp) {
+                *lp += feature_lp;
+            }
+        }
+
+        let mut result = HashMap::with_capacity(classes);
+        for ((class, log_prior), lp) in tables.classes.iter().zip(&tables.log_priors).zip(lp) {
+            let final_log_p = log_prior + lp;
+
+            if final_log_p.is_nan() || final_log_p == f64::INFINITY {
+                return Err(Error::InvalidScore {
+                    model_name: model_name.to_owned(),
+                    class: class.to_owned(),
+                    score: final_log_p,
+                });
+            }
+            result.insert(class.to_owned(), final_log_p);
+        }
+
+        Ok(Prediction::from_log_scores(result))
+    }
+
+    /// log(p(x_i | c_n)) of one feature for each class into `scores`, false if the feature
+    /// does not contribute to any class.
+    fn score_feature(
+        &self,
+        model_name: &str,
+        tables: &FrozenTables,
+        f: FeatureRef,
+        scores: &mut [f64],
+    ) -> Result<bool> {
+        let classes = scores.len();
+
+        match f.feature_type {
+            FeatureType::Text | FeatureType::Category => {
+                let (vocabulary, counts) = match tables.word_features.get(f.name) {
+                    Some(WordFeature {
+                        vocabulary, counts, ..
+                    }) => (vocabulary, counts),
+                    None => return Ok(false),
+                };
+
+                let mut words: Vec<(usize, f64)> = if f.feature_type == FeatureType::Text {
+                    let words = self.tokenize(f.name, f.value)?;
+                    count(&words, &self.stop_words)
+                        .into_iter()
+                        .filter_map(|(word, count)| Some((*vocabulary.get(word)?, count as f64)))
+                        .collect()
+                } else {
+                    vocabulary
+                        .get(f.value)
+                        .map(|row| (*row, 1.0))
+                        .into_iter()
+                        .collect()
+                };
+                words.sort_by_key(|(row, _)| *row);
+
+                scores.iter_mut().for_each(|score| *score = 0.0);
+                for (row, count) in words {
+                    let row = &counts.rows[row * classes..(row + 1) * classes];
+                    match &counts.norms {
+                        None => {
+                            for (score, d) in scores.iter_mut().zip(row) {
+                                *score += count * d;
+                            }
+                        }
+                        Some(norms) => {
+                            for ((score, weight), norm) in scores.iter_mut().zip(row).zip(norms) {
+                                *score += complement_score(count, *weight, *norm);
+                            }
+                        }
+                    }
+                }
+            }
+            FeatureType::Bernoulli => {
+                let (vocabulary, presence) = match tables.word_features.get(f.name) {
+                    Some(WordFeature {
+                        vocabulary,
+                        presence,
+                        ..
+                    }) => (vocabulary, presence),
+                    None => return Ok(false),
+                };
+
+                let words = self.tokenize(f.name, f.value)?;
+                let mut present_words: Vec<usize> = count(&words, &self.stop_words)
+                    .into_keys()
+                    .filter_map(|word| vocabulary.get(word).copied())
+                    .collect();
+                present_words.sort();
+
+                scores.copy_from_slice(&presence.all_absent);
+                for row in present_words {
+                    let row = &presence.rows[row * classes..(row + 1) * classes];
+                    for (score, d) in scores.iter_mut().zip(row) {
+                        *score += d;
+                    }
+                }
+            }
+            FeatureType::GaussianStd => {
+                let value = match parse_value(self.invalid_value_policy, model_name, f)? {
+                    Some(value) => value,
+                    None => return Ok(false),
+                };
+                match tables.gaussian_std_features.get(f.name) {
+                    Some(stats) => {
+                        for (score, (mu, sigma)) in scores.iter_mut().zip(stats) {
+                            *score = log_prob_gaussian(value, *mu, *sigma);
+                        }
+                    }
+                    None => {
+                        let lp = log_prob_gaussian(value, 0.0, gaussian_std_sigma(0.0, 0.0));
+                        scores.iter_mut().for_each(|score| *score = lp);
+                    }
+                }
+            }
+            FeatureType::Gaussian => {
+                let value = match parse_value(self.invalid_value_policy, model_name, f)? {
+                    Some(value) => value,
+                    None => return Ok(false),
+                };
+                match tables.gaussian_features.get(f.name) {
+                    Some((mus, sigma)) => {
+                        for (score, mu) in scores.iter_mut().zip(mus) {
+                            *score = log_prob_gaussian(value, *mu, *sigma);
+                        }
+                    }
+                    None => {
+                        let lp = log_prob_gaussian(value, 0.0, self.default_gaussian_sigma);
+                        scores.iter_mut().for_each(|score| *score = lp);
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn tokenize(&self, feature_name: &str, text: &str) -> Result<Vec<String>> {
+        let tokenizer = self
+            .feature_tokenizers
+            .get(feature_name)
+            .unwrap_or(&self.tokenizer)
+            .get(feature_name)?;
+        tokenize(
+            tokenizer,
+            self.feature_ngrams.get(feature_name),
+            &self.stop_words,
+            text,
+        )
+    }
+}
@@ -0,0 +1,65 @@
+use std::cmp::Ordering;
+
+use crate::{Model, ModelStore};
+
+impl<T: ModelStore + Sync> Model<T> {
+    /// The classes of a model with their prior counts `N_cn`, in the order of the classes.
+    pub fn classes(&self, model_name: &str) -> Vec<(String, f64)> {
+        self.model_store
+            .get_all_classes(model_name)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|class| {
+                let count = self.get_priors_count_of_class(model_name, self.id(&class));
+                (class, count)
+            })
+            .collect()
+    }
+
+    /// The features that have a vocabulary, `Text`, `Category` and `Bernoulli` ones,
+    /// with the number of distinct words seen in each.
+    pub fn vocabulary_sizes(&self, model_name: &str) -> Vec<(String, usize)> {
+        self.model_store
+            .get_all_word_features(model_name)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|feature_name| {
+                let size = self
+                    .model_store
+                    .get_all_words(model_name, &feature_name)
+                    .map_or(0, |words| words.len());
+                (feature_name, size)
+            })
+            .collect()
+    }
+
+    /// The `k` words of a feature counted most in a class, with their counts `count(x_i, c_n)`.
+    /// Words with the same count are in the order of the words.
+    pub fn top_words(
+        &self,
+        model_name: &str,
+        feature_name: &str,
+        class: &str,
+        k: usize,
+    ) -> Vec<(String, f64)> {
+        let feature = self.id(feature_name);
+        let class = self.id(class);
+        let mut words: Vec<(String, f64)> = self
+            .model_store
+            .get_all_words(model_name, feature_name)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|word| {
+                let count =
+                    self.get_count_of_word_in_class(model_name, feature, class, self.id(&word));
+                (word, count)
+            })
+            .filter(|(_, count)| *count > 0.0)
+            .collect();
+
+        // a stable sort keeps the words in order among the same counts
+        words.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+        words.truncate(k);
+        words
+    }
+}
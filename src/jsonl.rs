@@ -0,0 +1,146 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines, Write};
+use std::path::Path;
+
+use crate::{Error, Feature, Result};
+
+// A line of a JSON Lines file, see `JsonlReader`.
+#[derive(Deserialize)]
+struct Record {
+    #[serde(default)]
+    label: Option<String>,
+    features: Vec<Feature>,
+}
+
+#[derive(Serialize)]
+struct RecordRef<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<&'a str>,
+    features: &'a [Feature],
+}
+
+/// Reads examples from JSON Lines, one example per line:
+///
+/// ```json
+/// {"label": "spam", "features": [{"name": "title", "type": "Text", "value": "Win a prize"}]}
+/// ```
+///
+/// `type` is one of the names of `FeatureType`. The label can be left out for data to predict,
+/// it is empty then. Empty lines are skipped. Each example is read as `(label, features)`,
+/// ready for `Model::train` or, without the labels, `Model::predict_batch`.
+pub struct JsonlReader<R: BufRead> {
+    lines: Lines<R>,
+    line: usize,
+}
+
+impl JsonlReader<BufReader<File>> {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(JsonlReader::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: BufRead> JsonlReader<R> {
+    pub fn new(reader: R) -> Self {
+        JsonlReader {
+            lines: reader.lines(),
+            line: 0,
+        }
+    }
+
+    /// The next examples, up to `size` of them, none at the end of the file.
+    pub fn next_batch(&mut self, size: usize) -> Result<Vec<(String, Vec<Feature>)>> {
+        self.take(size).collect()
+    }
+}
+
+impl<R: BufRead> Iterator for JsonlReader<R> {
+    type Item = Result<(String, Vec<Feature>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let text = match self.lines.next()? {
+                Ok(text) => text,
+                Err(e) => return Some(Err(e.into())),
+            };
+            self.line += 1;
+
+            if text.trim().is_empty() {
+                continue;
+            }
+
+            return Some(
+                serde_json::from_str::<Record>(&text)
+                    .map(|r| (r.label.unwrap_or_default(), r.features))
+                    .map_err(|e| Error::Parse {
+                        line: self.line,
+                        message: e.to_string(),
+                    }),
+            );
+        }
+    }
+}
+
+/// Writes examples as JSON Lines, in the format `JsonlReader` reads.
+pub struct JsonlWriter<W: Write> {
+    writer: W,
+}
+
+impl JsonlWriter<File> {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(JsonlWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> JsonlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        JsonlWriter { writer }
+    }
+
+    /// Write an example, without a label if `label` is `None`.
+    pub fn write(&mut self, label: Option<&str>, features: &[Feature]) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, &RecordRef { label, features })?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[test]
+fn jsonl_works() {
+    use crate::FeatureType;
+
+    let features = vec![Feature {
+        feature_type: FeatureType::Text,
+        name: "title".to_owned(),
+        value: "Win a prize".to_owned(),
+    }];
+
+    let mut writer = JsonlWriter::new(vec![]);
+    writer.write(Some("spam"), &features).unwrap();
+    writer.write(None, &features).unwrap();
+    let text = String::from_utf8(writer.into_inner()).unwrap();
+    assert_eq!(
+        r#"{"label":"spam","features":[{"type":"Text","name":"title","value":"Win a prize"}]}"#,
+        text.lines().next().unwrap()
+    );
+
+    let text = text + "\n{\"label\": \"ham\", \"features\": [{\"name\": \"x\"}]}\n";
+    let mut reader = JsonlReader::new(text.as_bytes());
+    assert_eq!(
+        ("spam".to_owned(), features.clone()),
+        reader.next().unwrap().unwrap()
+    );
+    assert_eq!((String::new(), features), reader.next().unwrap().unwrap());
+    match reader.next().unwrap() {
+        Err(Error::Parse { line, .. }) => assert_eq!(4, line),
+        _ => panic!("expected a parse error"),
+    }
+    assert!(reader.next().is_none());
+}
@@ -1,28 +1,106 @@
+extern crate csv;
 extern crate rayon;
 extern crate regex;
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
 extern crate serde_regex;
+#[cfg(feature = "server")]
+extern crate tiny_http;
+extern crate toml;
+extern crate unicode_segmentation;
+
+mod error;
+pub mod eval;
+mod explain;
+mod frozen;
+mod inspect;
+mod jsonl;
+mod merge;
+mod persist;
+mod prediction;
+mod random;
+mod schema;
+pub mod search;
+#[cfg(feature = "server")]
+pub mod server;
+mod shared;
+mod tokenizer;
+
+pub use error::{Error, Result};
+pub use explain::{ClassExplanation, FeatureExplanation, TokenContribution};
+pub use frozen::FrozenModel;
+pub use jsonl::{JsonlReader, JsonlWriter};
+pub use persist::FORMAT_VERSION;
+pub use prediction::Prediction;
+pub use schema::{Column, CsvReader, LabelNormalization, Schema};
+pub use shared::SharedModel;
+pub use tokenizer::{
+    FnTokenizer, NGrams, RegexTokenizer, Tokenizer, TokenizerSpec, UnicodeWordTokenizer,
+    WhitespaceTokenizer, CHAR_GRAM_MARK,
+};
+
+use tokenizer::TokenizerSlot;
 
 use rayon::prelude::*;
-use regex::Regex;
-use std::collections::{BTreeSet, HashMap, HashSet};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::f64::consts::PI;
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
 
 use std::marker::Sync;
+use std::sync::RwLock;
 
-///
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A single named feature of an example, e.g. the body of an email or the age of a person.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Feature {
+    #[serde(rename = "type", alias = "feature_type")]
     pub feature_type: FeatureType,
     pub name: String,
     pub value: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A feature borrowing its name and value, to train and predict with data held elsewhere
+/// without copying it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeatureRef<'a> {
+    pub feature_type: FeatureType,
+    pub name: &'a str,
+    pub value: &'a str,
+}
+
+impl<'a> From<&'a Feature> for FeatureRef<'a> {
+    fn from(f: &'a Feature) -> Self {
+        FeatureRef {
+            feature_type: f.feature_type,
+            name: &f.name,
+            value: &f.value,
+        }
+    }
+}
+
+// A feature given to train or predict with, either an owned `Feature` or a `FeatureRef`
+// borrowing its name and value from data held elsewhere.
+trait AsFeatureRef {
+    fn as_feature_ref(&self) -> FeatureRef<'_>;
+}
+
+impl AsFeatureRef for Feature {
+    fn as_feature_ref(&self) -> FeatureRef<'_> {
+        self.into()
+    }
+}
+
+impl AsFeatureRef for FeatureRef<'_> {
+    fn as_feature_ref(&self) -> FeatureRef<'_> {
+        *self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum FeatureType {
     /// A multinomial feature and do word counting on feature.value.
     Text,
@@ -46,50 +124,288 @@ pub enum FeatureType {
     /// distribution otherwise this feature might easily dominate other features.
     /// If you are not sure, use the `Gaussian` type above.
     GaussianStd,
+
+    /// A Bernoulli feature, does word splitting on feature.value like `Text` but only takes
+    /// whether a word is present or not. It counts in how many examples of a class each word
+    /// appears, and when predicting also takes the words of the vocabulary that are absent into
+    /// account. Often works better than `Text` for short texts and keyword presence.
+    Bernoulli,
+}
+
+/// What to do with a `Gaussian` or `GaussianStd` feature value that is not a finite number,
+/// both in training and predicting.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum InvalidValuePolicy {
+    /// Ignore the feature as if it was not given. This is the default.
+    #[default]
+    Skip,
+
+    /// Give an `Error::InvalidValue` naming the model, the feature and its value.
+    Fail,
+
+    /// Use the given value instead.
+    Impute(f64),
+}
+
+/// The id of a class, feature or word name in a `ModelStore`, see `ModelStore::intern`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Id(pub u32);
+
+impl Id {
+    // the id of a name that was never interned, nothing is counted under it
+    const UNKNOWN: Id = Id(u32::MAX);
+}
+
+/// A statistic of a `Gaussian` or `GaussianStd` feature in a class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GaussianStat {
+    Count,
+    Mean,
+    /// Sum of squared differences from the mean, only kept for `GaussianStd`.
+    M2,
+}
+
+/// A counter of a model in a `ModelStore`, with the names it is about given by their ids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Key {
+    /// `N_cn`, the prior count of a class.
+    Prior(Id),
+
+    /// `N`, the sum of the prior counts of all classes.
+    Total,
+
+    /// `count(x_i, c_n)` of a feature, a class and a word. For `Bernoulli` features the number
+    /// of examples of the class the word appears in.
+    WordInClass(Id, Id, Id),
+
+    /// The count of all words of a feature in a class.
+    ClassTotal(Id, Id),
+
+    /// The count of a word of a feature in all classes together, the sum of its `WordInClass`.
+    WordInAllClasses(Id, Id),
+
+    /// The count of all words of a feature in all classes together, the sum of its `ClassTotal`.
+    AllClassesTotal(Id),
+
+    /// 1 for a word seen in a feature, 0 otherwise.
+    VocabMarker(Id, Id),
+
+    /// `|V|`, the number of distinct words seen in a feature.
+    VocabSize(Id),
+
+    /// The number of examples of a class having a `Bernoulli` feature.
+    ExamplesInClass(Id, Id),
+
+    /// A statistic of a `GaussianStd` feature in a class.
+    GaussianStd(GaussianStat, Id, Id),
+
+    /// A statistic of a `Gaussian` feature in a class.
+    Gaussian(GaussianStat, Id, Id),
+
+    /// The largest value of a `Gaussian` feature in all classes.
+    GaussianMax(Id),
+
+    /// The smallest value of a `Gaussian` feature in all classes.
+    GaussianMin(Id),
 }
 
 pub trait ModelStore {
-    fn map_add(&mut self, model_name: &str, prefix: &str, v: f64) -> f64;
+    /// The id of a class, feature or word name. The same name always gets the same id,
+    /// whatever model it is used in.
+    fn intern(&mut self, name: &str) -> Id;
+
+    /// The id of a name given by `intern` before, `None` if there is none and thus nothing
+    /// counted under the name.
+    fn lookup(&self, name: &str) -> Option<Id>;
+
+    fn map_add(&mut self, model_name: &str, key: Key, v: f64) -> f64;
 
-    fn map_add_with_default(&mut self, model_name: &str, prefix: &str, v: f64, default: f64)
-        -> f64;
+    fn map_add_with_default(&mut self, model_name: &str, key: Key, v: f64, default: f64) -> f64;
 
-    fn map_get(&self, model_name: &str, prefix: &str) -> f64;
+    fn map_get(&self, model_name: &str, key: Key) -> f64;
 
     fn save_class(&mut self, model_name: &str, class: &str);
 
+    fn remove_class(&mut self, model_name: &str, class: &str);
+
     fn get_all_classes(&self, model_name: &str) -> Option<BTreeSet<String>>;
+
+    /// The ids of the classes, in the order of `get_all_classes`.
+    fn get_all_class_ids(&self, model_name: &str) -> Vec<Id>;
+
+    fn save_word(&mut self, model_name: &str, feature_name: &str, word: &str);
+
+    fn remove_word(&mut self, model_name: &str, feature_name: &str, word: &str);
+
+    fn get_all_words(&self, model_name: &str, feature_name: &str) -> Option<BTreeSet<String>>;
+
+    /// Names of the features that have words, e.g. `Text` and `Category` features.
+    fn get_all_word_features(&self, model_name: &str) -> Option<BTreeSet<String>>;
+
+    /// Names of all models that have something counted.
+    fn get_all_models(&self) -> BTreeSet<String>;
+
+    /// Everything counted for a model.
+    fn get_all_counts(&self, model_name: &str) -> Vec<(Key, f64)>;
+
+    /// The name of an id given by `intern`.
+    fn name(&self, id: Id) -> Option<&str>;
+}
+
+// An example ready to be added to the counts of a model, see `Model::prepare_training`.
+// It borrows its class and feature names, or owns them to be kept, see `into_owned`.
+struct TrainingExample<'a> {
+    class: Cow<'a, str>,
+    weight: f64,
+    features: Vec<TrainingFeature<'a>>,
+}
+
+struct TrainingFeature<'a> {
+    name: Cow<'a, str>,
+    value: TrainingValue,
+}
+
+impl TrainingExample<'_> {
+    fn into_owned(self) -> TrainingExample<'static> {
+        TrainingExample {
+            class: Cow::Owned(self.class.into_owned()),
+            weight: self.weight,
+            features: self
+                .features
+                .into_iter()
+                .map(|f| TrainingFeature {
+                    name: Cow::Owned(f.name.into_owned()),
+                    value: f.value,
+                })
+                .collect(),
+        }
+    }
+}
+
+enum TrainingValue {
+    /// `Text` and `Category` words with their counts.
+    Words(Vec<(String, f64)>),
+    /// `Bernoulli` words present in the example.
+    PresentWords(Vec<String>),
+    GaussianStd(f64),
+    Gaussian(f64),
+    /// A feature value left out by the invalid value policy.
+    Skipped,
+}
+
+// A feature of an example ready to be scored against each class, see `Model::prepare_feature`.
+struct PreparedFeature<'a> {
+    name: &'a str,
+    feature: Id,
+    value: PreparedValue,
 }
 
+enum PreparedValue {
+    /// `Text` and `Category` words in the vocabulary with their counts.
+    Words(Vec<(Id, f64)>),
+    /// `Bernoulli` words in the vocabulary.
+    PresentWords(Vec<Id>),
+    GaussianStd(f64),
+    Gaussian(f64),
+    /// A feature value left out by the invalid value policy.
+    Skipped,
+}
+
+// A value computed over the whole vocabulary of a feature in a class, see `Model::log_prob_cache`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Cached {
+    /// sum_{w in V} log(1 - p_w) of a `Bernoulli` feature and a class.
+    BernoulliAllAbsent(Id, Id),
+    /// The sum of the absolute complement weights of a feature and a class.
+    ComplementNorm(Id, Id),
+}
+
+/// How `Text` and `Category` features are scored when predicting.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum Scoring {
+    /// Multinomial Naive Bayes, from the counts of words in the class itself. This is the default.
+    #[default]
+    Multinomial,
+
+    /// Complement Naive Bayes, from the counts of words in all other classes, a word that is
+    /// rare in the other classes scores high for the class. Works better than `Multinomial`
+    /// when some classes have much more training data than others. It uses the same counts,
+    /// so a trained model can switch to it without training again.
+    /// With `normalize_weights` the word weights of each class are divided by their sum over
+    /// the vocabulary, so that classes with long examples do not dominate.
+    Complement { normalize_weights: bool },
+}
+
+// counts at or below this are taken as zero, to allow for rounding errors when removing counts again
+const ZERO_COUNT: f64 = 1e-9;
+
+/// How the class priors `N_cn` and `N` are counted when training.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PriorCounting {
+    /// Count each training example once.
+    PerExample,
+
+    /// Count each training example once per feature it has, so examples with more features
+    /// weigh more in the priors. Models saved with a format version before 2 were trained like this,
+    /// they keep counting this way when loaded so that their counts stay consistent.
+    PerFeature,
+}
+
+// the prior counting of a serialized model without one, which was trained before it existed
+fn legacy_prior_counting() -> PriorCounting {
+    PriorCounting::PerFeature
+}
+
+/// A Naive Bayes model holding the counts of any number of named models in its `ModelStore`.
+///
+/// Use `Model::save` and `Model::load` to persist it, `load` also migrates files of older
+/// format versions. Deserializing a `Model` with serde yourself only reads the current layout.
 #[derive(Serialize, Deserialize)]
 pub struct Model<T: ModelStore + Sync> {
     // m2 init value, std at the beginning will be sqrt(default_gaussian_m2)
     default_gaussian_m2: f64,
     default_gaussian_sigma_factor: f64,
     model_store: T,
-    #[serde(with = "serde_regex")]
-    regex: Regex,
-    // Regex used on features, matches will be replaces by empty space. By default we use r"[^a-zA-Z]+" to replace every char not in English as space
+    tokenizer: TokenizerSlot, // tokenizer of text features, by default a RegexTokenizer keeping only English letters
+    #[serde(default)]
+    feature_tokenizers: HashMap<String, TokenizerSlot>, // tokenizers of certain text features by feature name
+    #[serde(default)]
+    feature_ngrams: HashMap<String, NGrams>, // n-grams of certain text features by feature name, single words if not set
     stop_words: Option<HashSet<String>>,
     pseudo_count: f64, // pseudo count of words in text feature
     prior_factor: f64, // influence of prior prob, default 1.0
+    #[serde(default)]
+    invalid_value_policy: InvalidValuePolicy,
+    #[serde(default)]
+    scoring: Scoring,
+    #[serde(default = "legacy_prior_counting")]
+    prior_counting: PriorCounting,
+    // values computed over a whole vocabulary by model name, cached for predicting and forgotten
+    // for the features and classes training changes
+    #[serde(skip)]
+    log_prob_cache: RwLock<HashMap<String, HashMap<Cached, f64>>>,
 }
 
 impl<T: ModelStore + Sync> Model<T> {
-    pub fn with_stop_words_file(mut self, stop_words_file: &str) -> Self {
-        let f = File::open(stop_words_file).unwrap();
+    pub fn with_stop_words_file(self, stop_words_file: &str) -> Self {
+        self.try_with_stop_words_file(stop_words_file)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_with_stop_words_file(mut self, stop_words_file: &str) -> Result<Self> {
+        let f = File::open(stop_words_file)?;
         let f = BufReader::new(&f);
 
         let mut stop_words = HashSet::new();
 
         for line in f.lines() {
-            let line = line.unwrap();
+            let line = line?;
             stop_words.insert(line);
         }
 
         self.stop_words = Some(stop_words);
 
-        self
+        Ok(self)
     }
 
     pub fn with_default_gaussian_m2(mut self, default_gaussian_m2: f64) -> Self {
@@ -99,6 +415,7 @@ impl<T: ModelStore + Sync> Model<T> {
 
     pub fn with_pseudo_count(mut self, pseudo_count: f64) -> Self {
         self.pseudo_count = pseudo_count;
+        self.clear_log_prob_cache();
         self
     }
 
@@ -115,66 +432,445 @@ impl<T: ModelStore + Sync> Model<T> {
         self
     }
 
+    /// Use this tokenizer for all `Text` features, except those given their own tokenizer
+    /// via `with_feature_tokenizer`.
+    pub fn with_tokenizer<K: Tokenizer + 'static>(mut self, tokenizer: K) -> Self {
+        self.tokenizer = TokenizerSlot::new(tokenizer);
+        self
+    }
+
+    pub fn with_feature_tokenizer<K: Tokenizer + 'static>(
+        mut self,
+        feature_name: &str,
+        tokenizer: K,
+    ) -> Self {
+        self.feature_tokenizers
+            .insert(feature_name.to_owned(), TokenizerSlot::new(tokenizer));
+        self
+    }
+
+    /// Count word and/or character n-grams of a `Text` feature instead of single words.
+    pub fn with_feature_ngrams(mut self, feature_name: &str, ngrams: NGrams) -> Self {
+        self.feature_ngrams.insert(feature_name.to_owned(), ngrams);
+        self
+    }
+
+    pub fn with_scoring(mut self, scoring: Scoring) -> Self {
+        self.scoring = scoring;
+        self
+    }
+
+    pub fn with_invalid_value_policy(mut self, invalid_value_policy: InvalidValuePolicy) -> Self {
+        self.invalid_value_policy = invalid_value_policy;
+        self
+    }
+
+    /// How this model counts class priors, `PriorCounting::PerFeature` flags a model
+    /// loaded from a file trained before priors were counted per example.
+    pub fn prior_counting(&self) -> PriorCounting {
+        self.prior_counting
+    }
+
+    /// Train the model, panics where `try_train` would return an error.
     pub fn train(&mut self, model_name: &str, class_feature_pairs: &[(String, Vec<Feature>)]) {
-        for (class, features) in class_feature_pairs {
-            for f in features {
-                self.add_to_priors_count_of_class(model_name, &class, 1.0);
-                self.add_to_total_data_count(model_name, 1.0);
-
-                match f.feature_type {
-                    FeatureType::Text => {
-                        let feature_value = clean_text(&f.value, &self.regex);
-                        let word_counts = count(&feature_value, &self.stop_words);
-                        for (word, count) in word_counts {
-                            self.add_to_count_of_word_in_class(
-                                model_name,
-                                &f.name,
-                                &class,
-                                word,
-                                count as f64,
-                            );
-                            self.add_to_count_of_all_word_in_class(
-                                model_name,
-                                &f.name,
-                                &class,
-                                count as f64,
-                            )
-                        }
-                    }
-                    FeatureType::Category => {
+        self.try_train(model_name, class_feature_pairs)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Train the model. If an error is returned nothing is trained.
+    pub fn try_train(
+        &mut self,
+        model_name: &str,
+        class_feature_pairs: &[(String, Vec<Feature>)],
+    ) -> Result<()> {
+        self.train_examples(model_name, class_feature_pairs)
+    }
+
+    /// As `train`, with examples borrowing their class and features, panics where
+    /// `try_train_ref` would return an error.
+    pub fn train_ref(&mut self, model_name: &str, class_feature_pairs: &[(&str, Vec<FeatureRef>)]) {
+        self.try_train_ref(model_name, class_feature_pairs)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// As `try_train`, with examples borrowing their class and features, so that nothing of
+    /// the examples is copied but the words counted.
+    pub fn try_train_ref(
+        &mut self,
+        model_name: &str,
+        class_feature_pairs: &[(&str, Vec<FeatureRef>)],
+    ) -> Result<()> {
+        self.train_examples(model_name, class_feature_pairs)
+    }
+
+    fn train_examples<C: AsRef<str>, F: AsFeatureRef>(
+        &mut self,
+        model_name: &str,
+        class_feature_pairs: &[(C, Vec<F>)],
+    ) -> Result<()> {
+        let examples = self.prepare_training(
+            model_name,
+            class_feature_pairs
+                .iter()
+                .map(|(class, features)| (class.as_ref(), 1.0, as_feature_refs(features))),
+        )?;
+        self.apply_training(model_name, &examples);
+        Ok(())
+    }
+
+    /// Train the model on examples as they come, panics where `try_train_iter` would return
+    /// an error.
+    pub fn train_iter<I: IntoIterator<Item = (String, Vec<Feature>)>>(
+        &mut self,
+        model_name: &str,
+        class_feature_pairs: I,
+    ) {
+        self.try_train_iter(model_name, class_feature_pairs)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Train the model on examples as they come, e.g. read from a file, keeping only one
+    /// example at a time in memory. Unlike `try_train` the examples before the one that
+    /// failed stay trained if an error is returned.
+    pub fn try_train_iter<I: IntoIterator<Item = (String, Vec<Feature>)>>(
+        &mut self,
+        model_name: &str,
+        class_feature_pairs: I,
+    ) -> Result<()> {
+        class_feature_pairs
+            .into_iter()
+            .try_for_each(|(class, features)| {
+                let example = self.prepare_training_example(
+                    model_name,
+                    &class,
+                    1.0,
+                    as_feature_refs(&features),
+                )?;
+                self.update(model_name, &example);
+                Ok(())
+            })
+    }
+
+    /// As `train_iter`, with examples borrowing their class and features, panics where
+    /// `try_train_iter_ref` would return an error.
+    pub fn train_iter_ref<'a, I: IntoIterator<Item = (&'a str, &'a [FeatureRef<'a>])>>(
+        &mut self,
+        model_name: &str,
+        class_feature_pairs: I,
+    ) {
+        self.try_train_iter_ref(model_name, class_feature_pairs)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// As `try_train_iter`, with examples borrowing their class and features, so that nothing
+    /// of the examples is copied but the words counted.
+    pub fn try_train_iter_ref<'a, I: IntoIterator<Item = (&'a str, &'a [FeatureRef<'a>])>>(
+        &mut self,
+        model_name: &str,
+        class_feature_pairs: I,
+    ) -> Result<()> {
+        class_feature_pairs
+            .into_iter()
+            .try_for_each(|(class, features)| {
+                let example = self.prepare_training_example(
+                    model_name,
+                    class,
+                    1.0,
+                    features.iter().copied(),
+                )?;
+                self.update(model_name, &example);
+                Ok(())
+            })
+    }
+
+    /// Train the model with a weight for each example, panics where `try_train_weighted`
+    /// would return an error.
+    pub fn train_weighted(
+        &mut self,
+        model_name: &str,
+        class_weight_feature_tuples: &[(String, f64, Vec<Feature>)],
+    ) {
+        self.try_train_weighted(model_name, class_weight_feature_tuples)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Train the model with a weight for each example, training an example with weight 2.0
+    /// is the same as training it twice. Weights scale every count the example contributes to,
+    /// so they need not be whole numbers but must be positive.
+    /// If an error is returned nothing is trained.
+    pub fn try_train_weighted(
+        &mut self,
+        model_name: &str,
+        class_weight_feature_tuples: &[(String, f64, Vec<Feature>)],
+    ) -> Result<()> {
+        let examples = self.prepare_weighted_training(model_name, class_weight_feature_tuples)?;
+        self.apply_training(model_name, &examples);
+        Ok(())
+    }
+
+    /// Remove previously trained examples from the model again, panics where `try_untrain`
+    /// would return an error.
+    pub fn untrain(&mut self, model_name: &str, class_feature_pairs: &[(String, Vec<Feature>)]) {
+        self.try_untrain(model_name, class_feature_pairs)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Remove previously trained examples from the model again, e.g. to relabel an example
+    /// untrain it with its old class then train it with the new one.
+    /// Every example has to be trained before, exactly as it is given here, otherwise the counts
+    /// of the model end up wrong. The only thing not reverted is the range of `Gaussian` features.
+    /// If an error is returned nothing is untrained.
+    pub fn try_untrain(
+        &mut self,
+        model_name: &str,
+        class_feature_pairs: &[(String, Vec<Feature>)],
+    ) -> Result<()> {
+        let examples = self.prepare_training(
+            model_name,
+            class_feature_pairs
+                .iter()
+                .map(|(class, features)| (class.as_ref(), -1.0, as_feature_refs(features))),
+        )?;
+        self.check_classes(model_name, &examples)?;
+        self.apply_training(model_name, &examples);
+        Ok(())
+    }
+
+    /// Check the weights and prepare examples for `apply_training`.
+    fn prepare_weighted_training<'a, C: AsRef<str>, F: AsFeatureRef>(
+        &self,
+        model_name: &str,
+        class_weight_feature_tuples: &'a [(C, f64, Vec<F>)],
+    ) -> Result<Vec<TrainingExample<'a>>> {
+        for (class, weight, _) in class_weight_feature_tuples {
+            if !(weight.is_finite() && *weight > 0.0) {
+                return Err(Error::InvalidWeight {
+                    model_name: model_name.to_owned(),
+                    class: class.as_ref().to_owned(),
+                    weight: *weight,
+                });
+            }
+        }
+        self.prepare_training(
+            model_name,
+            class_weight_feature_tuples
+                .iter()
+                .map(|(class, weight, features)| {
+                    (class.as_ref(), *weight, as_feature_refs(features))
+                }),
+        )
+    }
+
+    /// Tokenize and parse the features of examples of (class, weight, features), the part of
+    /// training that does not change the model. All examples are prepared before any is trained,
+    /// so that a failure does not leave a half trained model.
+    fn prepare_training<'a, I, F>(
+        &self,
+        model_name: &str,
+        examples: I,
+    ) -> Result<Vec<TrainingExample<'a>>>
+    where
+        I: Iterator<Item = (&'a str, f64, F)>,
+        F: IntoIterator<Item = FeatureRef<'a>>,
+    {
+        examples
+            .map(|(class, weight, features)| {
+                self.prepare_training_example(model_name, class, weight, features)
+            })
+            .collect()
+    }
+
+    fn prepare_training_example<'a, F: IntoIterator<Item = FeatureRef<'a>>>(
+        &self,
+        model_name: &str,
+        class: &'a str,
+        weight: f64,
+        features: F,
+    ) -> Result<TrainingExample<'a>> {
+        let features = features
+            .into_iter()
+            .map(|f| self.prepare_training_feature(model_name, f))
+            .collect::<Result<_>>()?;
+        Ok(TrainingExample {
+            class: Cow::Borrowed(class),
+            weight,
+            features,
+        })
+    }
+
+    fn prepare_training_feature<'a>(
+        &self,
+        model_name: &str,
+        f: FeatureRef<'a>,
+    ) -> Result<TrainingFeature<'a>> {
+        let value = match f.feature_type {
+            FeatureType::Text => {
+                let words = self.tokenize(f.name, f.value)?;
+                let word_counts = count(&words, &self.stop_words)
+                    .into_iter()
+                    .map(|(word, count)| (word.to_owned(), count as f64))
+                    .collect();
+                TrainingValue::Words(word_counts)
+            }
+            FeatureType::Category => TrainingValue::Words(vec![(f.value.to_owned(), 1.0)]),
+            FeatureType::Bernoulli => {
+                let words = self.tokenize(f.name, f.value)?;
+                let present_words = count(&words, &self.stop_words)
+                    .into_keys()
+                    .map(|word| word.to_owned())
+                    .collect();
+                TrainingValue::PresentWords(present_words)
+            }
+            FeatureType::GaussianStd => match self.parse_value(model_name, f)? {
+                Some(v) => TrainingValue::GaussianStd(v),
+                None => TrainingValue::Skipped,
+            },
+            FeatureType::Gaussian => match self.parse_value(model_name, f)? {
+                Some(v) => TrainingValue::Gaussian(v),
+                None => TrainingValue::Skipped,
+            },
+        };
+
+        Ok(TrainingFeature {
+            name: Cow::Borrowed(f.name),
+            value,
+        })
+    }
+
+    /// Give an `Error::UnknownClass` for an example of a class the model does not have,
+    /// which can not be untrained.
+    fn check_classes(&self, model_name: &str, examples: &[TrainingExample]) -> Result<()> {
+        let classes = self
+            .model_store
+            .get_all_classes(model_name)
+            .unwrap_or_default();
+        for example in examples {
+            if !classes.contains(example.class.as_ref()) {
+                return Err(Error::UnknownClass {
+                    model_name: model_name.to_owned(),
+                    class: example.class.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn tokenizer_for(&self, feature_name: &str) -> Result<&dyn Tokenizer> {
+        self.feature_tokenizers
+            .get(feature_name)
+            .unwrap_or(&self.tokenizer)
+            .get(feature_name)
+    }
+
+    /// The words of a text feature to count, after tokenizing and building n-grams.
+    fn tokenize(&self, feature_name: &str, text: &str) -> Result<Vec<String>> {
+        tokenize(
+            self.tokenizer_for(feature_name)?,
+            self.feature_ngrams.get(feature_name),
+            &self.stop_words,
+            text,
+        )
+    }
+
+    /// Add prepared examples to the counts of the model, a negative weight removes them again.
+    fn apply_training(&mut self, model_name: &str, examples: &[TrainingExample]) {
+        for example in examples {
+            self.update(model_name, example);
+        }
+    }
+
+    fn update(&mut self, model_name: &str, example: &TrainingExample) {
+        let class = example.class.as_ref();
+        let weight = example.weight;
+
+        for f in &example.features {
+            let name = f.name.as_ref();
+            match &f.value {
+                TrainingValue::Words(word_counts) => {
+                    for (word, count) in word_counts {
                         self.add_to_count_of_word_in_class(
-                            model_name, &f.name, &class, &f.value, 1.0,
+                            model_name,
+                            name,
+                            class,
+                            word,
+                            weight * count,
                         );
-                        self.add_to_count_of_all_word_in_class(model_name, &f.name, &class, 1.0)
+                        self.add_to_count_of_all_word_in_class(
+                            model_name,
+                            name,
+                            class,
+                            weight * count,
+                        )
+                    }
+                    // complement weights depend on the counts of all classes
+                    self.forget_cached(model_name, name, None);
+                }
+                TrainingValue::PresentWords(present_words) => {
+                    let feature = self.id(name);
+                    let vocabulary_size =
+                        self.get_count_of_unique_words_in_feature(model_name, feature);
+                    // the count of a word in class is the number of examples it appears in
+                    for word in present_words {
+                        self.add_to_count_of_word_in_class(model_name, name, class, word, weight);
                     }
-                    FeatureType::GaussianStd => match &f.value.parse::<f64>() {
-                        Ok(v) => self.gaussian_std_add(model_name, &f.name, &class, v.clone()),
-                        Err(e) => {
-                            println!(
-                                "FeatureType::GaussianStd parsing '{}' gives an error: {}. ",
-                                &f.value, e,
-                            );
-                            ()
-                        }
-                    },
-                    FeatureType::Gaussian => match &f.value.parse::<f64>() {
-                        Ok(v) => self.gaussian_add(model_name, &f.name, &class, v.clone()),
-                        Err(e) => {
-                            println!(
-                                "FeatureType::Gaussian parsing '{}' gives an error: {}. ",
-                                &f.value, e,
-                            );
-                            ()
-                        }
-                    },
+                    self.add_to_count_of_examples_in_class(model_name, name, class, weight);
+
+                    // a new or dropped word of the vocabulary is absent from examples of all classes
+                    let feature = self.id(name);
+                    if vocabulary_size
+                        == self.get_count_of_unique_words_in_feature(model_name, feature)
+                    {
+                        self.forget_cached(model_name, name, Some(class));
+                    } else {
+                        self.forget_cached(model_name, name, None);
+                    }
+                }
+                TrainingValue::GaussianStd(v) => {
+                    self.gaussian_std_add(model_name, name, class, *v, weight)
+                }
+                TrainingValue::Gaussian(v) => {
+                    self.gaussian_add(model_name, name, class, *v, weight)
                 }
+                TrainingValue::Skipped => {}
             }
         }
+
+        let prior_count = match self.prior_counting {
+            PriorCounting::PerExample => 1.0,
+            PriorCounting::PerFeature => example.features.len() as f64,
+        };
+        if prior_count > 0.0 {
+            self.add_to_priors_count_of_class(model_name, class, weight * prior_count);
+            self.add_to_total_data_count(model_name, weight * prior_count);
+        }
     }
 
+    /// Predict the probability of each class, panics where `try_predict` would return an error.
     pub fn predict(&self, model_name: &str, features: &[Feature]) -> HashMap<String, f64> {
-        self.predict_batch(&model_name, &[Vec::from(features)])
-            .remove(0)
+        self.try_predict(model_name, features)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_predict(
+        &self,
+        model_name: &str,
+        features: &[Feature],
+    ) -> Result<HashMap<String, f64>> {
+        Ok(self.scores(model_name, features)?.probabilities)
+    }
+
+    /// As `predict`, with features borrowing their name and value.
+    pub fn predict_ref(&self, model_name: &str, features: &[FeatureRef]) -> HashMap<String, f64> {
+        self.try_predict_ref(model_name, features)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_predict_ref(
+        &self,
+        model_name: &str,
+        features: &[FeatureRef],
+    ) -> Result<HashMap<String, f64>> {
+        Ok(self.scores(model_name, features)?.probabilities)
     }
 
     pub fn predict_batch(
@@ -182,134 +878,367 @@ impl<T: ModelStore + Sync> Model<T> {
         model_name: &str,
         features_vec: &[Vec<Feature>],
     ) -> Vec<HashMap<String, f64>> {
-        let outcomes = match self.model_store.get_all_classes(model_name) {
-            Some(c) => c,
-            None => return vec![HashMap::new()],
+        self.try_predict_batch(model_name, features_vec)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_predict_batch(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<Feature>],
+    ) -> Result<Vec<HashMap<String, f64>>> {
+        Ok(probabilities(self.batch_scores(model_name, features_vec)?))
+    }
+
+    /// As `predict_batch`, with features borrowing their name and value.
+    pub fn predict_batch_ref(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<FeatureRef>],
+    ) -> Vec<HashMap<String, f64>> {
+        self.try_predict_batch_ref(model_name, features_vec)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_predict_batch_ref(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<FeatureRef>],
+    ) -> Result<Vec<HashMap<String, f64>>> {
+        Ok(probabilities(self.batch_scores(model_name, features_vec)?))
+    }
+
+    /// Like `predict`, also giving the log scores of the classes.
+    pub fn predict_scores(&self, model_name: &str, features: &[Feature]) -> Prediction {
+        self.try_predict_scores(model_name, features)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_predict_scores(&self, model_name: &str, features: &[Feature]) -> Result<Prediction> {
+        self.scores(model_name, features)
+    }
+
+    pub fn predict_batch_scores(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<Feature>],
+    ) -> Vec<Prediction> {
+        self.try_predict_batch_scores(model_name, features_vec)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_predict_batch_scores(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<Feature>],
+    ) -> Result<Vec<Prediction>> {
+        self.batch_scores(model_name, features_vec)
+    }
+
+    fn scores<F: AsFeatureRef>(&self, model_name: &str, features: &[F]) -> Result<Prediction> {
+        match self.classes_with_log_priors(model_name) {
+            Some(classes) => self.predict_example(model_name, &classes, features),
+            None => Ok(Prediction::from_log_scores(HashMap::new())),
+        }
+    }
+
+    fn batch_scores<F: AsFeatureRef + Sync>(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<F>],
+    ) -> Result<Vec<Prediction>> {
+        let classes = match self.classes_with_log_priors(model_name) {
+            Some(classes) => classes,
+            // a model without classes gives no probabilities for each example
+            None => {
+                return Ok(features_vec
+                    .iter()
+                    .map(|_| Prediction::from_log_scores(HashMap::new()))
+                    .collect())
+            }
         };
 
+        features_vec
+            .par_iter() // use rayon for predicting in parallel
+            .map(|features| self.predict_example(model_name, &classes, features))
+            .collect()
+    }
+
+    /// The ids of the classes of a model with prior_factor * log(p(c_n)),
+    /// `None` if the model has no classes.
+    fn classes_with_log_priors(&self, model_name: &str) -> Option<Vec<(Id, f64)>> {
         let total_data_count = self.get_total_data_count(model_name);
 
-        let outcomes_to_priors_counts: Vec<(String, f64)> = outcomes
+        let classes: Vec<(Id, f64)> = self
+            .model_store
+            .get_all_class_ids(model_name)
             .into_iter()
-            .map(|outcome| {
-                let priors_count_of_class = self.get_priors_count_of_class(model_name, &outcome);
-                (outcome, priors_count_of_class)
+            .map(|class| {
+                let priors_count_of_class = self.get_priors_count_of_class(model_name, class);
+                let log_prior = self.cal_log_prior(priors_count_of_class, total_data_count);
+                (class, log_prior)
             })
             .collect();
+        Some(classes).filter(|classes| !classes.is_empty())
+    }
 
-        let results: Vec<HashMap<String, f64>> = features_vec
-            .par_iter() // use rayon for predicting in parallel
-            .map(|features| {
-                let mut result = HashMap::new();
-
-                for (outcome, priors_count_of_class) in &outcomes_to_priors_counts {
-                    let mut lp = 0.0;
-
-                    for f in features {
-                        let count_of_unique_words_in_feature =
-                            self.get_count_of_unique_words_in_feature(model_name, &f.name);
-
-                        let count_of_all_word_in_class =
-                            self.get_count_of_all_word_in_class(model_name, &f.name, &outcome);
-
-                        match f.feature_type {
-                            FeatureType::Text => {
-                                let feature_value = clean_text(&f.value, &self.regex);
-                                for (word, count) in count(&feature_value, &self.stop_words) {
-                                    if self.is_word_appeared_in_feature(model_name, &f.name, &word)
-                                    {
-                                        lp += self.cal_log_prob(
-                                            model_name,
-                                            &f.name,
-                                            &outcome,
-                                            count_of_unique_words_in_feature,
-                                            count_of_all_word_in_class,
-                                            count as f64,
-                                            word,
-                                        )
-                                    }
-                                }
-                            }
-                            FeatureType::Category => {
-                                if self.is_word_appeared_in_feature(model_name, &f.name, &f.value) {
-                                    lp += self.cal_log_prob(
-                                        model_name,
-                                        &f.name,
-                                        &outcome,
-                                        count_of_unique_words_in_feature,
-                                        count_of_all_word_in_class,
-                                        1.0,
-                                        &f.value,
-                                    )
-                                }
-                            }
-                            FeatureType::GaussianStd => match &f.value.parse::<f64>() {
-                                Ok(v) => {
-                                    lp += self.cal_log_prob_gaussian_std(
-                                        model_name,
-                                        &f.name,
-                                        &outcome,
-                                        v.clone(),
-                                    )
-                                }
-                                Err(e) => {
-                                    println!(
-                                        "FeatureType::GaussianStd parsing '{}' gives an error: {}. ",
-                                        &f.value, e,
-                                    );
-                                    ()
-                                }
-                            },
-                            FeatureType::Gaussian => match &f.value.parse::<f64>() {
-                                Ok(v) => {
-                                    lp += self.cal_log_prob_gaussian(
-                                        model_name,
-                                        &f.name,
-                                        &outcome,
-                                        v.clone(),
-                                    )
-                                }
-                                Err(e) => {
-                                    println!(
-                                        "FeatureType::Gaussian parsing '{}' gives an error: {}. ",
-                                        &f.value, e,
-                                    );
-                                    ()
-                                }
-                            },
-                        };
-                    }
+    fn predict_example<F: AsFeatureRef>(
+        &self,
+        model_name: &str,
+        classes: &[(Id, f64)],
+        features: &[F],
+    ) -> Result<Prediction> {
+        let mut result = HashMap::new();
+
+        let prepared = features
+            .iter()
+            .map(|f| self.prepare_feature(model_name, f.as_feature_ref()))
+            .collect::<Result<Vec<_>>>()?;
+
+        for (id, log_prior) in classes {
+            let class = self.model_store.name(*id).unwrap_or_default();
+            let mut lp = 0.0;
+
+            for f in &prepared {
+                lp += self.cal_log_prob_of_prepared(model_name, f, *id);
+            }
+
+            let final_log_p = log_prior + lp;
+
+            if final_log_p.is_nan() || final_log_p == f64::INFINITY {
+                return Err(Error::InvalidScore {
+                    model_name: model_name.to_owned(),
+                    class: class.to_owned(),
+                    score: final_log_p,
+                });
+            }
+            result.insert(class.to_owned(), final_log_p);
+        }
+
+        Ok(Prediction::from_log_scores(result))
+    }
+
+    /// The `k` most probable classes, most probable first. Classes with the same probability
+    /// are in the order of the classes, a NaN probability is taken as the lowest.
+    pub fn predict_top_k(
+        &self,
+        model_name: &str,
+        features: &[Feature],
+        k: usize,
+    ) -> Vec<(String, f64)> {
+        self.try_predict_top_k(model_name, features, k)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_predict_top_k(
+        &self,
+        model_name: &str,
+        features: &[Feature],
+        k: usize,
+    ) -> Result<Vec<(String, f64)>> {
+        Ok(top_k(self.try_predict(model_name, features)?, k))
+    }
+
+    /// The most probable class, `None` if the model has no classes.
+    pub fn predict_label(&self, model_name: &str, features: &[Feature]) -> Option<(String, f64)> {
+        self.try_predict_label(model_name, features)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_predict_label(
+        &self,
+        model_name: &str,
+        features: &[Feature],
+    ) -> Result<Option<(String, f64)>> {
+        Ok(top_k(self.try_predict(model_name, features)?, 1).pop())
+    }
+
+    pub fn predict_top_k_batch(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<Feature>],
+        k: usize,
+    ) -> Vec<Vec<(String, f64)>> {
+        self.try_predict_top_k_batch(model_name, features_vec, k)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
 
-                    let final_log_p = self.prior_factor
-                        * ((priors_count_of_class).ln() - (total_data_count).ln())
-                        + lp;
-                    result.insert(outcome.to_owned(), final_log_p);
+    pub fn try_predict_top_k_batch(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<Feature>],
+        k: usize,
+    ) -> Result<Vec<Vec<(String, f64)>>> {
+        Ok(self
+            .try_predict_batch(model_name, features_vec)?
+            .into_iter()
+            .map(|predictions| top_k(predictions, k))
+            .collect())
+    }
+
+    pub fn predict_label_batch(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<Feature>],
+    ) -> Vec<Option<(String, f64)>> {
+        self.try_predict_label_batch(model_name, features_vec)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_predict_label_batch(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<Feature>],
+    ) -> Result<Vec<Option<(String, f64)>>> {
+        Ok(self
+            .try_predict_top_k_batch(model_name, features_vec, 1)?
+            .into_iter()
+            .map(|mut top| top.pop())
+            .collect())
+    }
+
+    /// log(p(x_i | c_n)) of one feature, 0.0 for a feature that is skipped.
+    fn cal_log_prob_of_feature(&self, model_name: &str, f: FeatureRef, outcome: Id) -> Result<f64> {
+        let prepared = self.prepare_feature(model_name, f)?;
+        Ok(self.cal_log_prob_of_prepared(model_name, &prepared, outcome))
+    }
+
+    /// Tokenize a feature and look up the ids of its name and words, the part of scoring it that
+    /// is the same for all classes. Words not in the vocabulary of the feature are left out.
+    fn prepare_feature<'a>(
+        &self,
+        model_name: &str,
+        f: FeatureRef<'a>,
+    ) -> Result<PreparedFeature<'a>> {
+        let feature = self.id(f.name);
+
+        let value = match f.feature_type {
+            FeatureType::Text => {
+                let words = self.tokenize(f.name, f.value)?;
+                let mut words: Vec<(Id, f64)> = count(&words, &self.stop_words)
+                    .into_iter()
+                    .map(|(word, count)| (self.id(word), count as f64))
+                    .filter(|(word, _)| {
+                        self.is_word_appeared_in_feature(model_name, feature, *word)
+                    })
+                    .collect();
+                // summed in the order of the ids, so the same example always gets the same score
+                words.sort_by_key(|(word, _)| *word);
+                PreparedValue::Words(words)
+            }
+            FeatureType::Category => {
+                let word = self.id(f.value);
+                if self.is_word_appeared_in_feature(model_name, feature, word) {
+                    PreparedValue::Words(vec![(word, 1.0)])
+                } else {
+                    PreparedValue::Words(vec![])
                 }
+            }
+            FeatureType::Bernoulli => {
+                let words = self.tokenize(f.name, f.value)?;
+                let mut present_words: Vec<Id> = count(&words, &self.stop_words)
+                    .into_keys()
+                    .map(|word| self.id(word))
+                    .filter(|word| self.is_word_appeared_in_feature(model_name, feature, *word))
+                    .collect();
+                present_words.sort();
+                PreparedValue::PresentWords(present_words)
+            }
+            FeatureType::GaussianStd => match self.parse_value(model_name, f)? {
+                Some(v) => PreparedValue::GaussianStd(v),
+                None => PreparedValue::Skipped,
+            },
+            FeatureType::Gaussian => match self.parse_value(model_name, f)? {
+                Some(v) => PreparedValue::Gaussian(v),
+                None => PreparedValue::Skipped,
+            },
+        };
 
-                normalize(result)
-            })
-            .collect();
+        Ok(PreparedFeature {
+            name: f.name,
+            feature,
+            value,
+        })
+    }
+
+    fn cal_log_prob_of_prepared(&self, model_name: &str, f: &PreparedFeature, outcome: Id) -> f64 {
+        match &f.value {
+            PreparedValue::Words(words) => {
+                let count_of_unique_words_in_feature =
+                    self.get_count_of_unique_words_in_feature(model_name, f.feature);
+                let count_of_all_word_in_class =
+                    self.get_count_of_all_word_in_class(model_name, f.feature, outcome);
+
+                words
+                    .iter()
+                    .map(|(word, count)| {
+                        self.cal_log_prob(
+                            model_name,
+                            f.name,
+                            f.feature,
+                            outcome,
+                            count_of_unique_words_in_feature,
+                            count_of_all_word_in_class,
+                            *count,
+                            *word,
+                        )
+                    })
+                    .sum()
+            }
+            PreparedValue::PresentWords(present_words) => {
+                self.cal_log_prob_bernoulli(model_name, f.name, f.feature, outcome, present_words)
+            }
+            PreparedValue::GaussianStd(v) => {
+                self.cal_log_prob_gaussian_std(model_name, f.feature, outcome, *v)
+            }
+            PreparedValue::Gaussian(v) => {
+                self.cal_log_prob_gaussian(model_name, f.feature, outcome, *v)
+            }
+            PreparedValue::Skipped => 0.0,
+        }
+    }
+
+    /// prior_factor * log(p(c_n))
+    fn cal_log_prior(&self, priors_count_of_class: f64, total_data_count: f64) -> f64 {
+        self.prior_factor * (priors_count_of_class.ln() - total_data_count.ln())
+    }
+
+    /// Parse the value of a `Gaussian` or `GaussianStd` feature, applying the invalid value policy
+    /// of the model to a value that is not a finite number. `None` means the feature is skipped.
+    fn parse_value(&self, model_name: &str, f: FeatureRef) -> Result<Option<f64>> {
+        parse_value(self.invalid_value_policy, model_name, f)
+    }
 
-        results
+    // the id of a name, an id that nothing is counted under for a name never seen
+    fn id(&self, name: &str) -> Id {
+        self.model_store.lookup(name).unwrap_or(Id::UNKNOWN)
     }
 
     fn add_to_priors_count_of_class(&mut self, model_name: &str, c: &str, v: f64) {
-        self.model_store
-            .map_add(model_name, &format!("_Ncn|{}", c), v); // _Ncn: priors_count_of_class
-
-        self.model_store.save_class(model_name, c);
+        let class = self.model_store.intern(c);
+        let count = self.model_store.map_add(model_name, Key::Prior(class), v);
+
+        // however small a weight, training adds the class, only removing counts drops it
+        if v > 0.0 {
+            self.model_store.save_class(model_name, c);
+        } else if count <= ZERO_COUNT {
+            // back to exactly zero, so that rounding errors are not counted as the class
+            self.model_store
+                .map_add(model_name, Key::Prior(class), -count);
+            self.model_store.remove_class(model_name, c);
+        }
     }
 
-    fn get_priors_count_of_class(&self, model_name: &str, c: &str) -> f64 {
-        self.model_store.map_get(model_name, &format!("_Ncn|{}", c)) // _Ncn: priors_count_of_class
+    fn get_priors_count_of_class(&self, model_name: &str, c: Id) -> f64 {
+        self.model_store.map_get(model_name, Key::Prior(c))
     }
 
     fn add_to_total_data_count(&mut self, model_name: &str, v: f64) {
-        self.model_store.map_add(model_name, "_N", v); //_N: sum of all prior count N_cn of all classes c_n.
+        self.model_store.map_add(model_name, Key::Total, v);
     }
 
     fn get_total_data_count(&self, model_name: &str) -> f64 {
-        self.model_store.map_get(model_name, "_N") //_N: sum of all prior count N_cn of all classes c_n.
+        self.model_store.map_get(model_name, Key::Total)
     }
 
     fn add_to_count_of_word_in_class(
@@ -320,26 +1249,28 @@ impl<T: ModelStore + Sync> Model<T> {
         word: &str,
         v: f64,
     ) {
-        self.model_store.map_add(
-            model_name,
-            &format!("_c_f_c|{}|{}|{}", feature_name, c, word),
-            v,
-        );
+        let feature = self.model_store.intern(feature_name);
+        let word_id = self.model_store.intern(word);
+        let key = Key::WordInClass(feature, self.model_store.intern(c), word_id);
+        self.model_store.map_add(model_name, key, v);
+        self.model_store
+            .map_add(model_name, Key::WordInAllClasses(feature, word_id), v);
 
-        self.add_unique_word_in_feature(model_name, feature_name, word);
+        if v > 0.0 {
+            self.add_unique_word_in_feature(model_name, feature_name, word);
+        } else {
+            self.remove_unique_word_in_feature_if_unused(model_name, feature_name, word);
+        }
     }
 
-    fn get_count_of_word_in_class(
-        &self,
-        model_name: &str,
-        feature_name: &str,
-        c: &str,
-        word: &str,
-    ) -> f64 {
-        self.model_store.map_get(
-            model_name,
-            &format!("_c_f_c|{}|{}|{}", feature_name, c, word),
-        )
+    fn get_count_of_word_in_class(&self, model_name: &str, feature: Id, c: Id, word: Id) -> f64 {
+        self.model_store
+            .map_get(model_name, Key::WordInClass(feature, c, word))
+    }
+
+    fn get_count_of_word_in_all_classes(&self, model_name: &str, feature: Id, word: Id) -> f64 {
+        self.model_store
+            .map_get(model_name, Key::WordInAllClasses(feature, word))
     }
 
     fn add_to_count_of_all_word_in_class(
@@ -349,80 +1280,233 @@ impl<T: ModelStore + Sync> Model<T> {
         c: &str,
         v: f64,
     ) {
+        let feature = self.model_store.intern(feature_name);
+        let key = Key::ClassTotal(feature, self.model_store.intern(c));
+        self.model_store.map_add(model_name, key, v);
+        self.model_store
+            .map_add(model_name, Key::AllClassesTotal(feature), v);
+    }
+
+    fn get_count_of_all_word_in_class(&self, model_name: &str, feature: Id, c: Id) -> f64 {
         self.model_store
-            .map_add(model_name, &format!("_c_c|{}|{}", feature_name, c), v);
+            .map_get(model_name, Key::ClassTotal(feature, c))
     }
 
-    fn get_count_of_all_word_in_class(&self, model_name: &str, feature_name: &str, c: &str) -> f64 {
+    fn get_count_of_all_word_in_all_classes(&self, model_name: &str, feature: Id) -> f64 {
         self.model_store
-            .map_get(model_name, &format!("_c_c|{}|{}", feature_name, c))
+            .map_get(model_name, Key::AllClassesTotal(feature))
     }
 
     fn add_unique_word_in_feature(&mut self, model_name: &str, feature_name: &str, word: &str) {
-        if !self.is_word_appeared_in_feature(model_name, feature_name, word) {
-            self.model_store.map_add(
-                model_name,
-                &format!("_Vw|{}|{}", feature_name, word), // _Vw: marker for unique word in feature
-                1.0,
-            );
+        let feature = self.model_store.intern(feature_name);
+        let word_id = self.model_store.intern(word);
+        if !self.is_word_appeared_in_feature(model_name, feature, word_id) {
+            self.model_store
+                .map_add(model_name, Key::VocabMarker(feature, word_id), 1.0);
             self.model_store
-                .map_add(model_name, &format!("_V|{}", feature_name), 1.0);
+                .map_add(model_name, Key::VocabSize(feature), 1.0);
+            self.model_store.save_word(model_name, feature_name, word);
         }
     }
-    fn is_word_appeared_in_feature(
-        &self,
+    fn remove_unique_word_in_feature_if_unused(
+        &mut self,
         model_name: &str,
         feature_name: &str,
         word: &str,
-    ) -> bool {
+    ) {
+        let feature = self.id(feature_name);
+        let word_id = self.id(word);
+        let count_of_word = self.get_count_of_word_in_all_classes(model_name, feature, word_id);
+
+        if count_of_word <= ZERO_COUNT
+            && self.is_word_appeared_in_feature(model_name, feature, word_id)
+        {
+            self.model_store
+                .map_add(model_name, Key::VocabMarker(feature, word_id), -1.0);
+            self.model_store
+                .map_add(model_name, Key::VocabSize(feature), -1.0);
+            self.model_store.remove_word(model_name, feature_name, word);
+        }
+    }
+    fn is_word_appeared_in_feature(&self, model_name: &str, feature: Id, word: Id) -> bool {
         0 != self
             .model_store
-            .map_get(model_name, &format!("_Vw|{}|{}", feature_name, word)) as usize // _Vw: marker for unique word in feature
+            .map_get(model_name, Key::VocabMarker(feature, word)) as usize
+    }
+    fn get_count_of_unique_words_in_feature(&self, model_name: &str, feature: Id) -> f64 {
+        self.model_store
+            .map_get(model_name, Key::VocabSize(feature))
+    }
+
+    //
+    // Bernoulli session
+    //
+    fn add_to_count_of_examples_in_class(
+        &mut self,
+        model_name: &str,
+        feature_name: &str,
+        c: &str,
+        v: f64,
+    ) {
+        let key = Key::ExamplesInClass(
+            self.model_store.intern(feature_name),
+            self.model_store.intern(c),
+        );
+        self.model_store.map_add(model_name, key, v);
     }
-    fn get_count_of_unique_words_in_feature(&self, model_name: &str, feature_name: &str) -> f64 {
+
+    fn get_count_of_examples_in_class(&self, model_name: &str, feature: Id, c: Id) -> f64 {
         self.model_store
-            .map_get(model_name, &format!("_V|{}", feature_name))
+            .map_get(model_name, Key::ExamplesInClass(feature, c))
+    }
+
+    /// log(p(x | c_n)) with the given words present and all other words of the vocabulary absent.
+    /// With p_w the probability of word w to be present in an example of class c_n:
+    ///     sum_{w in V} log(1 - p_w) + sum_{w present} (log(p_w) - log(1 - p_w))
+    /// The first sum does not depend on the example, so it is cached instead of going through
+    /// the vocabulary on every prediction.
+    fn cal_log_prob_bernoulli(
+        &self,
+        model_name: &str,
+        feature_name: &str,
+        feature: Id,
+        outcome: Id,
+        present_words: &[Id],
+    ) -> f64 {
+        let count_of_examples = self.get_count_of_examples_in_class(model_name, feature, outcome);
+
+        let mut lp = self.cal_log_prob_bernoulli_all_absent(
+            model_name,
+            feature_name,
+            feature,
+            outcome,
+            count_of_examples,
+        );
+
+        for word in present_words {
+            let count_of_word_in_class =
+                self.get_count_of_word_in_class(model_name, feature, outcome, *word);
+            lp += log_prob_present(count_of_word_in_class, count_of_examples, self.pseudo_count)
+                - log_prob_absent(count_of_word_in_class, count_of_examples, self.pseudo_count);
+        }
+        lp
+    }
+
+    fn cal_log_prob_bernoulli_all_absent(
+        &self,
+        model_name: &str,
+        feature_name: &str,
+        feature: Id,
+        outcome: Id,
+        count_of_examples: f64,
+    ) -> f64 {
+        let key = Cached::BernoulliAllAbsent(feature, outcome);
+        if let Some(lp) = self.cached(model_name, key) {
+            return lp;
+        }
+
+        let lp = self
+            .model_store
+            .get_all_words(model_name, feature_name)
+            .unwrap_or_default()
+            .iter()
+            .map(|word| {
+                let count_of_word_in_class =
+                    self.get_count_of_word_in_class(model_name, feature, outcome, self.id(word));
+                log_prob_absent(count_of_word_in_class, count_of_examples, self.pseudo_count)
+            })
+            .sum();
+
+        self.log_prob_cache
+            .write()
+            .unwrap()
+            .entry(model_name.to_owned())
+            .or_default()
+            .insert(key, lp);
+        lp
+    }
+
+    fn cached(&self, model_name: &str, key: Cached) -> Option<f64> {
+        self.log_prob_cache
+            .read()
+            .unwrap()
+            .get(model_name)
+            .and_then(|cached| cached.get(&key))
+            .copied()
+    }
+
+    fn clear_log_prob_cache(&mut self) {
+        self.log_prob_cache.get_mut().unwrap().clear();
     }
 
-    ///
-    /// GaussianStd session
-    ///
+    // Forget the cached values of a feature of a model, of one class or of all classes.
+    fn forget_cached(&mut self, model_name: &str, feature_name: &str, class: Option<&str>) {
+        let feature = self.id(feature_name);
+        let class = class.map(|c| self.id(c));
+        if let Some(cached) = self.log_prob_cache.get_mut().unwrap().get_mut(model_name) {
+            cached.retain(|key, _| {
+                let (f, c) = match *key {
+                    Cached::BernoulliAllAbsent(f, c) | Cached::ComplementNorm(f, c) => (f, c),
+                };
+                f != feature || class.is_some_and(|class| class != c)
+            });
+        }
+    }
+    // end of Bernoulli session
+
+    //
+    // GaussianStd session
+    //
     fn gaussian_std_add(
         &mut self,
         model_name: &str,
         feature_name: &str,
         outcome: &str,
         value: f64,
+        weight: f64,
     ) {
-        //     count += 1
+        //     count += weight
         //     val delta = x - mean
-        //     mean += delta / count
+        //     mean += delta * weight / count
         //     val delta2 = x - mean
-        //     m2 += delta * delta2
-        let count = self.model_store.map_add(
-            model_name,
-            &format!("_Gstd_count|{}|{}", feature_name, outcome),
-            1.0,
-        );
+        //     m2 += weight * delta * delta2
+        // a negative weight reverts an earlier add with the same value
+        let feature = self.model_store.intern(feature_name);
+        let outcome = self.model_store.intern(outcome);
+        let count_key = Key::GaussianStd(GaussianStat::Count, feature, outcome);
+        let mean_key = Key::GaussianStd(GaussianStat::Mean, feature, outcome);
+        let m2_key = Key::GaussianStd(GaussianStat::M2, feature, outcome);
+
+        let count = self.model_store.map_add(model_name, count_key, weight);
+
+        let mean = self.model_store.map_get(model_name, mean_key);
+
+        if weight < 0.0 && count <= ZERO_COUNT {
+            // everything removed, back to the initial state
+            self.model_store.map_add(model_name, count_key, -count);
+            self.model_store.map_add(model_name, mean_key, -mean);
+            let m2 = self.model_store.map_get(model_name, m2_key);
+            self.model_store.map_add_with_default(
+                model_name,
+                m2_key,
+                self.default_gaussian_m2 - m2,
+                self.default_gaussian_m2,
+            );
+            return;
+        }
 
-        let mean = self.model_store.map_get(
-            model_name,
-            &format!("_Gstd_mean|{}|{}", feature_name, outcome),
-        );
         let delta = value - mean;
 
-        let mean = self.model_store.map_add(
-            model_name,
-            &format!("_Gstd_mean|{}|{}", feature_name, outcome),
-            delta / count,
-        ); // mean += delta / count
+        let mean = self
+            .model_store
+            .map_add(model_name, mean_key, delta * weight / count); // mean += delta * weight / count
 
         let delta2 = value - mean;
 
         self.model_store.map_add_with_default(
             model_name,
-            &format!("_Gstd_m2|{}|{}", feature_name, outcome),
-            delta * delta2,
+            m2_key,
+            weight * delta * delta2,
             self.default_gaussian_m2, // m2 init value, std at the beginning will be sqrt(default_gaussian_m2)
         );
     }
@@ -430,8 +1514,8 @@ impl<T: ModelStore + Sync> Model<T> {
     fn cal_log_prob_gaussian_std(
         &self,
         model_name: &str,
-        feature_name: &str,
-        outcome: &str,
+        feature: Id,
+        outcome: Id,
         value: f64,
     ) -> f64 {
         // private fun logPropabilityOutcome(outcome: Outcome, value: Double): Double {
@@ -449,209 +1533,542 @@ impl<T: ModelStore + Sync> Model<T> {
 
         let mu = self.model_store.map_get(
             model_name,
-            &format!("_Gstd_mean|{}|{}", feature_name, outcome),
+            Key::GaussianStd(GaussianStat::Mean, feature, outcome),
         );
         let count = self.model_store.map_get(
             model_name,
-            &format!("_Gstd_count|{}|{}", feature_name, outcome),
+            Key::GaussianStd(GaussianStat::Count, feature, outcome),
         );
         let m2 = self.model_store.map_get(
             model_name,
-            &format!("_Gstd_m2|{}|{}", feature_name, outcome),
+            Key::GaussianStd(GaussianStat::M2, feature, outcome),
         );
 
-        let mut sigma;
-        if count >= 2.0 {
-            sigma = (m2 / (count - 1.0)).sqrt();
-        } else {
-            sigma = 1.0; // simple assumption to make prediction work better even if only trained once
-        }
-        if sigma < 1.0 {
-            sigma = 1.0; // do not allow sigma smaller than ore, prevent over taken other features
-                         // when using Gaussian feature here, one should make the input larger than 1
-                         // different with blayze, where it lets function returns 0 if happens
-        }
-
-        // from Kotlin blayze code:
-        // -ln(sigma) - ln(sqrt(2 * PI)) - (value - mu).pow(2).div(2 * sigma.pow(2))
-        // switch the last part, google "-log(1+x**2), -(x**2)" to see difference
-        -sigma.ln()
-            - (2.0 * PI).sqrt().ln()
-            - (1.0 + (value - mu).powi(2) / (2.0 * sigma.powi(2))).ln()
+        log_prob_gaussian(value, mu, gaussian_std_sigma(count, m2))
     }
-    /// end of GaussianStd session
+    // end of GaussianStd session
 
-    ///
-    /// Gaussian session
-    ///
-    fn gaussian_add(&mut self, model_name: &str, feature_name: &str, outcome: &str, value: f64) {
-        //     count += 1
+    //
+    // Gaussian session
+    //
+    fn gaussian_add(
+        &mut self,
+        model_name: &str,
+        feature_name: &str,
+        outcome: &str,
+        value: f64,
+        weight: f64,
+    ) {
+        //     count += weight
         //     val delta = x - mean
-        //     mean += delta / count
+        //     mean += delta * weight / count
         //     sigma = (max - min) / 6
-        let count = self.model_store.map_add(
-            model_name,
-            &format!("_G_count|{}|{}", feature_name, outcome),
-            1.0,
-        );
+        // a negative weight reverts an earlier add with the same value, except for max and min
+        let feature = self.model_store.intern(feature_name);
+        let outcome = self.model_store.intern(outcome);
+        let count_key = Key::Gaussian(GaussianStat::Count, feature, outcome);
+        let mean_key = Key::Gaussian(GaussianStat::Mean, feature, outcome);
+
+        let count = self.model_store.map_add(model_name, count_key, weight);
+
+        let mean = self.model_store.map_get(model_name, mean_key);
+
+        if weight < 0.0 && count <= ZERO_COUNT {
+            // everything removed, back to the initial state
+            self.model_store.map_add(model_name, count_key, -count);
+            self.model_store.map_add(model_name, mean_key, -mean);
+            return;
+        }
 
-        let mean = self
-            .model_store
-            .map_get(model_name, &format!("_G_mean|{}|{}", feature_name, outcome));
         let delta = value - mean;
 
-        self.model_store.map_add(
-            model_name,
-            &format!("_G_mean|{}|{}", feature_name, outcome),
-            delta / count,
-        ); // mean += delta / count
+        self.model_store
+            .map_add(model_name, mean_key, delta * weight / count); // mean += delta * weight / count
+
+        if weight < 0.0 {
+            return;
+        }
 
         // add max
         let max = self
             .model_store
-            .map_get(model_name, &format!("_G_max|{}", feature_name));
+            .map_get(model_name, Key::GaussianMax(feature));
         if value > max {
             self.model_store
-                .map_add(model_name, &format!("_G_max|{}", feature_name), value - max);
+                .map_add(model_name, Key::GaussianMax(feature), value - max);
         }
 
         // add min
         let min = self
             .model_store
-            .map_get(model_name, &format!("_G_min|{}", feature_name));
+            .map_get(model_name, Key::GaussianMin(feature));
         if value < min {
             self.model_store
-                .map_add(model_name, &format!("_G_min|{}", feature_name), value - min);
+                .map_add(model_name, Key::GaussianMin(feature), value - min);
         }
     }
 
-    fn cal_log_prob_gaussian(
-        &self,
-        model_name: &str,
-        feature_name: &str,
-        outcome: &str,
-        value: f64,
-    ) -> f64 {
-        let mu = self
-            .model_store
-            .map_get(model_name, &format!("_G_mean|{}|{}", feature_name, outcome));
+    fn cal_log_prob_gaussian(&self, model_name: &str, feature: Id, outcome: Id, value: f64) -> f64 {
+        let mu = self.model_store.map_get(
+            model_name,
+            Key::Gaussian(GaussianStat::Mean, feature, outcome),
+        );
 
         let max = self
             .model_store
-            .map_get(model_name, &format!("_G_max|{}", feature_name));
+            .map_get(model_name, Key::GaussianMax(feature));
         let min = self
             .model_store
-            .map_get(model_name, &format!("_G_min|{}", feature_name));
-
-        let sigma = ((max - min) * self.default_gaussian_sigma_factor).max(1e-3);
+            .map_get(model_name, Key::GaussianMin(feature));
 
-        // from Kotlin blayze code:
-        // -ln(sigma) - ln(sqrt(2 * PI)) - (value - mu).pow(2).div(2 * sigma.pow(2))
-        // switch the last part, google "-log(1+x**2), -(x**2)" to see difference
-        -sigma.ln()
-            - (2.0 * PI).sqrt().ln()
-            - (1.0 + (value - mu).powi(2) / (2.0 * sigma.powi(2))).ln()
+        log_prob_gaussian(
+            value,
+            mu,
+            gaussian_sigma(max, min, self.default_gaussian_sigma_factor),
+        )
     }
-    /// end of Gaussian session
+    // end of Gaussian session
 
+    #[allow(clippy::too_many_arguments)]
     fn cal_log_prob(
         &self,
         model_name: &str,
         feature_name: &str,
-        outcome: &str,
+        feature: Id,
+        outcome: Id,
         count_of_unique_words_in_feature: f64, // |V|
         count_of_all_word_in_class: f64,
         count_of_word: f64,
-        word: &str,
+        word: Id,
     ) -> f64 {
         let count_of_word_in_class =
-            self.get_count_of_word_in_class(model_name, feature_name, outcome, word);
+            self.get_count_of_word_in_class(model_name, feature, outcome, word);
+
+        match self.scoring {
+            Scoring::Multinomial => log_prob(
+                count_of_word,                    // t_i
+                count_of_word_in_class,           // c_f_c
+                count_of_all_word_in_class,       // c_c
+                count_of_unique_words_in_feature, // |V|
+                self.pseudo_count,
+            ),
+            Scoring::Complement { normalize_weights } => {
+                let weight = self.cal_complement_weight(
+                    model_name,
+                    feature,
+                    outcome,
+                    count_of_unique_words_in_feature,
+                    word,
+                );
+                let norm = if normalize_weights {
+                    self.cal_complement_weight_norm(
+                        model_name,
+                        feature_name,
+                        outcome,
+                        count_of_unique_words_in_feature,
+                    )
+                } else {
+                    0.0
+                };
+                complement_score(count_of_word, weight, norm)
+            }
+        }
+    }
+
+    //
+    // Complement session
+    //
+    /// log of the probability of a word in all classes other than c_n, the weight of the word
+    /// in complement Naive Bayes. A word frequent in other classes has a high (less negative)
+    /// weight, which counts against class c_n.
+    fn cal_complement_weight(
+        &self,
+        model_name: &str,
+        feature: Id,
+        outcome: Id,
+        count_of_unique_words_in_feature: f64,
+        word: Id,
+    ) -> f64 {
+        let count_of_word_in_other_classes = self
+            .get_count_of_word_in_all_classes(model_name, feature, word)
+            - self.get_count_of_word_in_class(model_name, feature, outcome, word);
+        let count_of_all_word_in_other_classes = self
+            .get_count_of_all_word_in_all_classes(model_name, feature)
+            - self.get_count_of_all_word_in_class(model_name, feature, outcome);
 
         log_prob(
-            count_of_word,                    // t_i
-            count_of_word_in_class,           // c_f_c
-            count_of_all_word_in_class,       // c_c
-            count_of_unique_words_in_feature, // |V|
+            1.0,
+            count_of_word_in_other_classes.max(0.0),
+            count_of_all_word_in_other_classes.max(0.0),
+            count_of_unique_words_in_feature,
             self.pseudo_count,
         )
     }
+
+    /// Sum of the absolute complement weights of all words of the vocabulary, cached
+    /// as it does not depend on the example. The sums of all classes are computed together,
+    /// going through the vocabulary once.
+    fn cal_complement_weight_norm(
+        &self,
+        model_name: &str,
+        feature_name: &str,
+        outcome: Id,
+        count_of_unique_words_in_feature: f64,
+    ) -> f64 {
+        let feature = self.id(feature_name);
+        if let Some(norm) = self.cached(model_name, Cached::ComplementNorm(feature, outcome)) {
+            return norm;
+        }
+
+        let classes = self.model_store.get_all_class_ids(model_name);
+        // as cal_complement_weight, with the counts not depending on the word taken out of the loop
+        let count_of_all_word_in_all_classes =
+            self.get_count_of_all_word_in_all_classes(model_name, feature);
+        let counts_of_all_word_in_other_classes: Vec<f64> = classes
+            .iter()
+            .map(|class| {
+                count_of_all_word_in_all_classes
+                    - self.get_count_of_all_word_in_class(model_name, feature, *class)
+            })
+            .collect();
+
+        let mut norms = vec![0.0; classes.len()];
+        for word in self
+            .model_store
+            .get_all_words(model_name, feature_name)
+            .unwrap_or_default()
+        {
+            let word = self.id(&word);
+            let count_of_word_in_all_classes =
+                self.get_count_of_word_in_all_classes(model_name, feature, word);
+            for ((class, count_of_all_word_in_other_classes), norm) in classes
+                .iter()
+                .zip(&counts_of_all_word_in_other_classes)
+                .zip(norms.iter_mut())
+            {
+                let count_of_word_in_other_classes = count_of_word_in_all_classes
+                    - self.get_count_of_word_in_class(model_name, feature, *class, word);
+                *norm += log_prob(
+                    1.0,
+                    count_of_word_in_other_classes.max(0.0),
+                    count_of_all_word_in_other_classes.max(0.0),
+                    count_of_unique_words_in_feature,
+                    self.pseudo_count,
+                )
+                .abs();
+            }
+        }
+
+        let mut cache = self.log_prob_cache.write().unwrap();
+        let cached = cache.entry(model_name.to_owned()).or_default();
+        for (class, norm) in classes.into_iter().zip(norms) {
+            cached.insert(Cached::ComplementNorm(feature, class), norm);
+        }
+        cached
+            .get(&Cached::ComplementNorm(feature, outcome))
+            .copied()
+            .unwrap_or(0.0)
+    }
+    // end of Complement session
 }
 
 // A in memory ModelStore implementation ModelHashMapStore
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ModelHashMapStore {
-    map: HashMap<String, f64>,
-    class_map: HashMap<String, BTreeSet<String>>, // model_name to list of class
+    names: Interner,
+    models: HashMap<String, ModelCounts>, // model_name to everything counted for the model
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ModelCounts {
+    #[serde(
+        serialize_with = "serialize_counts",
+        deserialize_with = "deserialize_counts"
+    )]
+    counts: HashMap<Key, f64>,
+    classes: BTreeSet<String>,
+    words: BTreeMap<String, BTreeSet<String>>, // feature name to vocabulary
+}
+
+// the counts are saved as a list of pairs, json objects only have strings as keys
+fn serialize_counts<S: serde::Serializer>(
+    counts: &HashMap<Key, f64>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_seq(counts.iter())
+}
+
+fn deserialize_counts<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<HashMap<Key, f64>, D::Error> {
+    let pairs: Vec<(Key, f64)> = serde::Deserialize::deserialize(deserializer)?;
+    Ok(pairs.into_iter().collect())
+}
+
+// Names and their ids, the id of a name is its index in `names`. Saved as the list of names.
+#[derive(Debug, Clone, Default)]
+struct Interner {
+    ids: HashMap<String, Id>,
+    names: Vec<String>,
+}
+
+impl Interner {
+    fn intern(&mut self, name: &str) -> Id {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = Id(self.names.len() as u32);
+        self.ids.insert(name.to_owned(), id);
+        self.names.push(name.to_owned());
+        id
+    }
+}
+
+impl serde::Serialize for Interner {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        self.names.serialize(serializer)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Interner {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let names: Vec<String> = serde::Deserialize::deserialize(deserializer)?;
+        let ids = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.to_owned(), Id(i as u32)))
+            .collect();
+        Ok(Interner { ids, names })
+    }
+}
+
+impl Default for Model<ModelHashMapStore> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: ModelStore + Sync + Clone> Clone for Model<T> {
+    fn clone(&self) -> Self {
+        Model {
+            model_store: self.model_store.clone(),
+            tokenizer: self.tokenizer.clone(),
+            feature_tokenizers: self.feature_tokenizers.clone(),
+            feature_ngrams: self.feature_ngrams.clone(),
+            stop_words: self.stop_words.clone(),
+            default_gaussian_m2: self.default_gaussian_m2,
+            default_gaussian_sigma_factor: self.default_gaussian_sigma_factor,
+            pseudo_count: self.pseudo_count,
+            prior_factor: self.prior_factor,
+            invalid_value_policy: self.invalid_value_policy,
+            scoring: self.scoring,
+            prior_counting: self.prior_counting,
+            log_prob_cache: RwLock::new(HashMap::new()),
+        }
+    }
 }
 
 impl Model<ModelHashMapStore> {
     pub fn new() -> Model<ModelHashMapStore> {
         Model::<ModelHashMapStore> {
-            model_store: ModelHashMapStore {
-                map: HashMap::new(),
-                class_map: HashMap::new(),
-            },
-            regex: Regex::new(r"[^a-zA-Z]+").unwrap(), // only keep any kind of letter from any language, others become space
+            model_store: ModelHashMapStore::default(),
+            tokenizer: TokenizerSlot::new(RegexTokenizer::default()),
+            feature_tokenizers: HashMap::new(),
+            feature_ngrams: HashMap::new(),
             stop_words: None,
             default_gaussian_m2: 0.0,
             default_gaussian_sigma_factor: 1.0 / 6.0,
             pseudo_count: 1.0,
             prior_factor: 1.0,
+            invalid_value_policy: InvalidValuePolicy::Skip,
+            scoring: Scoring::Multinomial,
+            prior_counting: PriorCounting::PerExample,
+            log_prob_cache: RwLock::new(HashMap::new()),
         }
     }
 }
 
 impl ModelStore for ModelHashMapStore {
-    fn map_add(&mut self, model_name: &str, prefix: &str, v: f64) -> f64 {
-        self.map_add_with_default(model_name, prefix, v, 0.0)
+    fn intern(&mut self, name: &str) -> Id {
+        self.names.intern(name)
     }
 
-    fn map_add_with_default(
-        &mut self,
-        model_name: &str,
-        prefix: &str,
-        v: f64,
-        default: f64,
-    ) -> f64 {
-        let key = format!("{}|{}", model_name, prefix);
-        let old_value = self.map.entry(key).or_insert(default);
+    fn lookup(&self, name: &str) -> Option<Id> {
+        self.names.ids.get(name).copied()
+    }
+
+    fn map_add(&mut self, model_name: &str, key: Key, v: f64) -> f64 {
+        self.map_add_with_default(model_name, key, v, 0.0)
+    }
+
+    fn map_add_with_default(&mut self, model_name: &str, key: Key, v: f64, default: f64) -> f64 {
+        let counts = match self.models.get_mut(model_name) {
+            Some(model) => &mut model.counts,
+            None => &mut self.models.entry(model_name.to_owned()).or_default().counts,
+        };
+        let old_value = counts.entry(key).or_insert(default);
         *old_value += v;
         *old_value
     }
 
-    fn map_get(&self, model_name: &str, prefix: &str) -> f64 {
-        let key = format!("{}|{}", model_name, prefix);
-        *self.map.get(&key).unwrap_or_else(|| &0.0)
+    fn map_get(&self, model_name: &str, key: Key) -> f64 {
+        self.models
+            .get(model_name)
+            .and_then(|model| model.counts.get(&key))
+            .copied()
+            .unwrap_or(0.0)
     }
 
     fn save_class(&mut self, model_name: &str, class: &str) {
-        // add class to class_map
-        let class_vec = self
-            .class_map
-            .entry(model_name.to_string())
-            .or_insert(BTreeSet::new());
-        class_vec.insert(class.to_string());
+        let model = self.models.entry(model_name.to_owned()).or_default();
+        if !model.classes.contains(class) {
+            model.classes.insert(class.to_owned());
+        }
+    }
+
+    fn remove_class(&mut self, model_name: &str, class: &str) {
+        if let Some(model) = self.models.get_mut(model_name) {
+            model.classes.remove(class);
+        }
     }
 
     fn get_all_classes(&self, model_name: &str) -> Option<BTreeSet<String>> {
-        self.class_map.get(model_name).cloned()
+        self.models
+            .get(model_name)
+            .filter(|model| !model.classes.is_empty())
+            .map(|model| model.classes.clone())
+    }
+
+    fn get_all_class_ids(&self, model_name: &str) -> Vec<Id> {
+        self.models
+            .get(model_name)
+            .map(|model| {
+                model
+                    .classes
+                    .iter()
+                    .filter_map(|class| self.lookup(class))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn save_word(&mut self, model_name: &str, feature_name: &str, word: &str) {
+        self.models
+            .entry(model_name.to_owned())
+            .or_default()
+            .words
+            .entry(feature_name.to_owned())
+            .or_default()
+            .insert(word.to_owned());
+    }
+
+    fn remove_word(&mut self, model_name: &str, feature_name: &str, word: &str) {
+        if let Some(words) = self
+            .models
+            .get_mut(model_name)
+            .and_then(|model| model.words.get_mut(feature_name))
+        {
+            words.remove(word);
+        }
+    }
+
+    fn get_all_words(&self, model_name: &str, feature_name: &str) -> Option<BTreeSet<String>> {
+        self.models
+            .get(model_name)
+            .and_then(|model| model.words.get(feature_name))
+            .cloned()
+    }
+
+    fn get_all_word_features(&self, model_name: &str) -> Option<BTreeSet<String>> {
+        self.models
+            .get(model_name)
+            .filter(|model| !model.words.is_empty())
+            .map(|model| model.words.keys().cloned().collect())
+    }
+
+    fn get_all_models(&self) -> BTreeSet<String> {
+        self.models.keys().cloned().collect()
+    }
+
+    fn get_all_counts(&self, model_name: &str) -> Vec<(Key, f64)> {
+        self.models
+            .get(model_name)
+            .map(|model| model.counts.iter().map(|(k, v)| (*k, *v)).collect())
+            .unwrap_or_default()
+    }
+
+    fn name(&self, id: Id) -> Option<&str> {
+        self.names
+            .names
+            .get(id.0 as usize)
+            .map(|name| name.as_str())
     }
 }
 
-///
-/// private util functions
-///
+//
+// private util functions
+//
+
+fn as_feature_refs<F: AsFeatureRef>(features: &[F]) -> impl Iterator<Item = FeatureRef<'_>> {
+    features.iter().map(|f| f.as_feature_ref())
+}
+
+fn probabilities(predictions: Vec<Prediction>) -> Vec<HashMap<String, f64>> {
+    predictions
+        .into_iter()
+        .map(|prediction| prediction.probabilities)
+        .collect()
+}
+
+/// The words of a text feature to count, after tokenizing and building n-grams.
+fn tokenize(
+    tokenizer: &dyn Tokenizer,
+    ngrams: Option<&NGrams>,
+    stop_words: &Option<HashSet<String>>,
+    text: &str,
+) -> Result<Vec<String>> {
+    let mut words = tokenizer.tokenize(text);
+
+    match ngrams {
+        Some(ngrams) => {
+            // stop words are removed before they are joined into n-grams
+            if let Some(stop_words) = stop_words {
+                words.retain(|w| !stop_words.contains(w));
+            }
+            words.retain(|w| !w.is_empty());
+            Ok(ngrams.apply(&words))
+        }
+        None => Ok(words),
+    }
+}
 
-fn clean_text(text: &str, regex: &Regex) -> String {
-    let text = regex.replace_all(&text, " ");
-    let text = text.trim().to_lowercase();
-    text
+/// Parse the value of a `Gaussian` or `GaussianStd` feature, `None` means the feature is skipped.
+fn parse_value(policy: InvalidValuePolicy, model_name: &str, f: FeatureRef) -> Result<Option<f64>> {
+    match f.value.parse::<f64>() {
+        Ok(v) if v.is_finite() => Ok(Some(v)),
+        _ => match policy {
+            InvalidValuePolicy::Skip => Ok(None),
+            InvalidValuePolicy::Impute(v) => Ok(Some(v)),
+            InvalidValuePolicy::Fail => Err(Error::InvalidValue {
+                model_name: model_name.to_owned(),
+                feature_name: f.name.to_owned(),
+                value: f.value.to_owned(),
+            }),
+        },
+    }
 }
 
-fn count<'a>(text: &'a str, stop_words: &Option<HashSet<String>>) -> HashMap<&'a str, usize> {
-    let counts = text
-        .split(" ")
+fn count<'a>(words: &'a [String], stop_words: &Option<HashSet<String>>) -> HashMap<&'a str, usize> {
+    let counts = words
+        .iter()
+        .map(|w| w.as_str())
+        .filter(|w| !w.is_empty())
         .filter(|w| match stop_words {
             Some(stop_words_set) => !stop_words_set.contains(*w),
             None => true,
@@ -663,6 +2080,19 @@ fn count<'a>(text: &'a str, stop_words: &Option<HashSet<String>>) -> HashMap<&'a
     counts
 }
 
+/// Sort predictions by probability, highest first, and keep the first k. Classes are kept in
+/// their order, the order of their names, among the same probabilities, NaN sorts last.
+fn top_k(predictions: HashMap<String, f64>, k: usize) -> Vec<(String, f64)> {
+    let mut ranked: Vec<(String, f64)> = predictions.into_iter().collect();
+    ranked.sort_by(|(ka, va), (kb, vb)| {
+        vb.partial_cmp(va)
+            .unwrap_or_else(|| va.is_nan().cmp(&vb.is_nan()))
+            .then_with(|| ka.cmp(kb))
+    });
+    ranked.truncate(k);
+    ranked
+}
+
 /// c_f_c: count_of_word_in_class
 /// c_c:
 /// v: count_of_unique_words_in_feature
@@ -670,32 +2100,66 @@ fn log_prob(count: f64, c_f_c: f64, c_c: f64, v: f64, pseudo_count: f64) -> f64
     count * ((c_f_c + pseudo_count).ln() - (c_c + v * pseudo_count).ln())
 }
 
-fn normalize(mut predictions: HashMap<String, f64>) -> HashMap<String, f64> {
-    let max = &predictions
-        .values()
-        .max_by(|a, b| a.partial_cmp(b).unwrap())
-        .unwrap()
-        .clone();
+/// -count * weight / norm, the score of a word in complement Naive Bayes.
+fn complement_score(count: f64, weight: f64, norm: f64) -> f64 {
+    // all weights are 0 with a vocabulary of a single word, nothing to normalize then
+    if norm > 0.0 {
+        -count * weight / norm
+    } else {
+        -count * weight
+    }
+}
 
-    for (_, v) in &mut predictions {
-        *v = (*v - max).exp();
+/// sigma of a `GaussianStd` feature in a class from the count and m2 of its values
+fn gaussian_std_sigma(count: f64, m2: f64) -> f64 {
+    let mut sigma;
+    if count >= 2.0 {
+        sigma = (m2 / (count - 1.0)).sqrt();
+    } else {
+        sigma = 1.0; // simple assumption to make prediction work better even if only trained once
+    }
+    if sigma < 1.0 {
+        sigma = 1.0; // do not allow sigma smaller than ore, prevent over taken other features
+                     // when using Gaussian feature here, one should make the input larger than 1
+                     // different with blayze, where it lets function returns 0 if happens
     }
+    sigma
+}
+
+/// sigma of a `Gaussian` feature, the same in all classes
+fn gaussian_sigma(max: f64, min: f64, sigma_factor: f64) -> f64 {
+    ((max - min) * sigma_factor).max(1e-3)
+}
 
-    let norm: f64 = predictions.values().sum();
+fn log_prob_gaussian(value: f64, mu: f64, sigma: f64) -> f64 {
+    // from Kotlin blayze code:
+    // -ln(sigma) - ln(sqrt(2 * PI)) - (value - mu).pow(2).div(2 * sigma.pow(2))
+    // switch the last part, google "-log(1+x**2), -(x**2)" to see difference
+    -sigma.ln() - (2.0 * PI).sqrt().ln() - (1.0 + (value - mu).powi(2) / (2.0 * sigma.powi(2))).ln()
+}
 
-    for (_, v) in &mut predictions {
-        *v = *v / norm;
-    }
+/// log of the probability that a word is present in an example of a class
+/// c_f_c: count of examples of the class with the word
+/// c_e: count of examples of the class
+fn log_prob_present(c_f_c: f64, c_e: f64, pseudo_count: f64) -> f64 {
+    (c_f_c + pseudo_count).ln() - (c_e + 2.0 * pseudo_count).ln()
+}
 
-    predictions
+fn log_prob_absent(c_f_c: f64, c_e: f64, pseudo_count: f64) -> f64 {
+    (c_e - c_f_c + pseudo_count).ln() - (c_e + 2.0 * pseudo_count).ln()
 }
 
-///
-///
-///
+//
+//
+//
 #[test]
 fn count_works() {
-    let result = count("This is good good ... Rust Rust Rust", &None);
+    let words: Vec<String> = "This is good good ... Rust  Rust Rust"
+        .split(' ')
+        .map(|w| w.to_owned())
+        .collect();
+    let result = count(&words, &None);
+    assert!(!result.contains_key(""));
     assert_eq!(2, result["good"]);
     assert_eq!(1, result["This"]);
     assert_eq!(1, result["is"]);
@@ -703,34 +2167,21 @@ fn count_works() {
 }
 
 #[test]
-fn clean_text_works() {
-    let text = "This is &/some weird TEXT";
-
-    let cleaned_text = clean_text(text, &Regex::new(r"[^a-zA-Z]+").unwrap());
-    assert_eq!("this is some weird text", cleaned_text);
-}
-
-#[test]
-fn normalize_works() {
+fn top_k_works() {
     let mut map = HashMap::new();
-    map.insert("a".to_owned(), 1.0);
-    map.insert("b".to_owned(), 5.0);
+    map.insert("c".to_owned(), 0.25);
+    map.insert("b".to_owned(), 0.5);
+    map.insert("a".to_owned(), 0.25);
+    map.insert("d".to_owned(), f64::NAN);
 
-    let map = normalize(map);
-    assert_eq!(0.017986209962091555, *map.get("a").unwrap());
-    assert_eq!(0.9820137900379085, *map.get("b").unwrap());
-
-    // Noticing an interesting character for normalize:
-    // If you add same value onto the original values, the normalized result won't change.
-    // We utilize this character on prediction to allow log probability calculated also for those
-    // unknown words
-    let mut map = HashMap::new();
-    map.insert("a".to_owned(), 1.0 + 500.0);
-    map.insert("b".to_owned(), 5.0 + 500.0);
-
-    let map = normalize(map);
-    assert_eq!(0.017986209962091555, *map.get("a").unwrap());
-    assert_eq!(0.9820137900379085, *map.get("b").unwrap());
+    assert_eq!(
+        vec![("b".to_owned(), 0.5), ("a".to_owned(), 0.25)],
+        top_k(map.clone(), 2)
+    );
+    let ranked = top_k(map, 10);
+    assert_eq!(4, ranked.len());
+    assert_eq!("c", ranked[2].0);
+    assert_eq!("d", ranked[3].0);
 }
 
 #[test]
@@ -749,11 +2200,11 @@ fn model_count_classes_works() {
     let model = Model::new();
     assert_eq!(
         0,
-        model.get_priors_count_of_class("test_model", "class_1") as usize
+        model.get_priors_count_of_class("test_model", model.id("class_1")) as usize
     );
     assert_eq!(
         0,
-        model.get_priors_count_of_class("test_model", "class_2") as usize
+        model.get_priors_count_of_class("test_model", model.id("class_2")) as usize
     );
     assert_eq!(None, model.model_store.get_all_classes("test_model"));
 
@@ -762,7 +2213,7 @@ fn model_count_classes_works() {
     model.add_to_priors_count_of_class("test_model", "class_1", 1.0);
     assert_eq!(
         1,
-        model.get_priors_count_of_class("test_model", "class_1") as usize
+        model.get_priors_count_of_class("test_model", model.id("class_1")) as usize
     );
     assert_eq!(
         1,
@@ -776,7 +2227,7 @@ fn model_count_classes_works() {
     model.add_to_priors_count_of_class("test_model", "class_1", 10.0);
     assert_eq!(
         11,
-        model.get_priors_count_of_class("test_model", "class_1") as usize
+        model.get_priors_count_of_class("test_model", model.id("class_1")) as usize
     );
     assert_eq!(
         1,
@@ -790,7 +2241,7 @@ fn model_count_classes_works() {
     model.add_to_priors_count_of_class("test_model", "class_2", 10.0);
     assert_eq!(
         10,
-        model.get_priors_count_of_class("test_model", "class_2") as usize
+        model.get_priors_count_of_class("test_model", model.id("class_2")) as usize
     );
     assert_eq!(
         2,
@@ -804,7 +2255,7 @@ fn model_count_classes_works() {
     model.add_to_priors_count_of_class("test_model", "class_2", 10.0);
     assert_eq!(
         20,
-        model.get_priors_count_of_class("test_model", "class_2") as usize
+        model.get_priors_count_of_class("test_model", model.id("class_2")) as usize
     );
     assert_eq!(
         2,
@@ -815,3 +2266,55 @@ fn model_count_classes_works() {
             .len()
     );
 }
+
+#[test]
+fn training_forgets_only_changed_cached_values() {
+    let tags = |class: &str, value: &str| {
+        (
+            class.to_owned(),
+            vec![Feature {
+                feature_type: FeatureType::Bernoulli,
+                name: "tags".to_owned(),
+                value: value.to_owned(),
+            }],
+        )
+    };
+    let cached = |model: &Model<ModelHashMapStore>, model_name: &str, class: &str| {
+        model.cached(
+            model_name,
+            Cached::BernoulliAllAbsent(model.id("tags"), model.id(class)),
+        )
+    };
+
+    let mut model = Model::new();
+    let examples = vec![tags("a", "x y"), tags("b", "y")];
+    model.train("m", &examples);
+    model.train("other", &examples);
+    let input = vec![tags("", "x").1.remove(0)];
+    model.predict("m", &input);
+    model.predict("other", &input);
+    assert!(cached(&model, "m", "a").is_some() && cached(&model, "m", "b").is_some());
+
+    // the vocabulary stays the same, only the class trained changes
+    model.train("m", &[tags("a", "x")]);
+    assert!(cached(&model, "m", "a").is_none());
+    assert!(cached(&model, "m", "b").is_some());
+    assert!(cached(&model, "other", "a").is_some());
+
+    // a new word is absent from the examples of all classes
+    model.train("m", &[tags("a", "z")]);
+    assert!(cached(&model, "m", "b").is_none());
+    assert!(cached(&model, "other", "b").is_some());
+
+    let mut expected = Model::new();
+    expected.train(
+        "m",
+        &[
+            tags("a", "x y"),
+            tags("b", "y"),
+            tags("a", "x"),
+            tags("a", "z"),
+        ],
+    );
+    assert_eq!(expected.predict("m", &input), model.predict("m", &input));
+}
@@ -0,0 +1,231 @@
+use rayon::prelude::*;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::RwLock;
+
+use crate::{Feature, GaussianStat, Id, Key, Model, ModelHashMapStore, ModelStore, Result};
+
+impl<T: ModelStore + Sync> Model<T> {
+    /// Train the model on all threads of rayon, panics where `try_train_par` would return
+    /// an error.
+    pub fn train_par(&mut self, model_name: &str, class_feature_pairs: &[(String, Vec<Feature>)]) {
+        self.try_train_par(model_name, class_feature_pairs)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Train the model as `try_train` does, splitting the examples among the threads of rayon.
+    /// Each thread trains a model of its own, which are then merged into this one with `merge`.
+    /// If an error is returned nothing is trained.
+    pub fn try_train_par(
+        &mut self,
+        model_name: &str,
+        class_feature_pairs: &[(String, Vec<Feature>)],
+    ) -> Result<()> {
+        let chunk_size = class_feature_pairs.len() / rayon::current_num_threads() + 1;
+
+        let partial_models = class_feature_pairs
+            .par_chunks(chunk_size)
+            .map(|chunk| {
+                let mut partial_model = self.empty_like();
+                partial_model.try_train(model_name, chunk)?;
+                Ok(partial_model)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        for partial_model in &partial_models {
+            self.merge(partial_model);
+        }
+        Ok(())
+    }
+
+    /// Add everything `other` has counted to this model, as if the examples `other` was trained
+    /// with were trained with this model too. Counts are summed, the mean and variance of
+    /// `GaussianStd` features are combined by the parallel Welford algorithm, and the means of
+    /// `Gaussian` features are weighted by their counts.
+    ///
+    /// Both models should have the same settings, e.g. the same tokenizers and prior counting,
+    /// only the counts of `other` are taken.
+    pub fn merge<S: ModelStore + Sync>(&mut self, other: &Model<S>) {
+        for model_name in other.model_store.get_all_models() {
+            self.merge_model(&model_name, other);
+        }
+        self.clear_log_prob_cache();
+    }
+
+    fn merge_model<S: ModelStore + Sync>(&mut self, model_name: &str, other: &Model<S>) {
+        let mut gaussian_std = BTreeSet::new();
+        let mut gaussian = BTreeSet::new();
+
+        for (key, v) in other.model_store.get_all_counts(model_name) {
+            let name = |id: Id| other.model_store.name(id).unwrap_or_default();
+
+            match key {
+                Key::Prior(c) => self.add_to_priors_count_of_class(model_name, name(c), v),
+                Key::Total => self.add_to_total_data_count(model_name, v),
+                Key::WordInClass(f, c, w) => {
+                    let feature = self.model_store.intern(name(f));
+                    let word = self.model_store.intern(name(w));
+                    let key = Key::WordInClass(feature, self.model_store.intern(name(c)), word);
+                    self.model_store.map_add(model_name, key, v);
+                    self.model_store
+                        .map_add(model_name, Key::WordInAllClasses(feature, word), v);
+                }
+                Key::ClassTotal(f, c) => {
+                    self.add_to_count_of_all_word_in_class(model_name, name(f), name(c), v)
+                }
+                // counted along with the counts in each class
+                Key::WordInAllClasses(..) | Key::AllClassesTotal(_) => {}
+                Key::VocabMarker(f, w) => {
+                    if other.is_word_appeared_in_feature(model_name, f, w) {
+                        self.add_unique_word_in_feature(model_name, name(f), name(w));
+                    }
+                }
+                // counted along with the vocabulary markers
+                Key::VocabSize(_) => {}
+                Key::ExamplesInClass(f, c) => {
+                    self.add_to_count_of_examples_in_class(model_name, name(f), name(c), v)
+                }
+                Key::GaussianStd(_, f, c) => {
+                    gaussian_std.insert((f, c));
+                }
+                Key::Gaussian(_, f, c) => {
+                    gaussian.insert((f, c));
+                }
+                Key::GaussianMax(f) => {
+                    let key = Key::GaussianMax(self.model_store.intern(name(f)));
+                    let max = self.model_store.map_get(model_name, key);
+                    if v > max {
+                        self.model_store.map_add(model_name, key, v - max);
+                    }
+                }
+                Key::GaussianMin(f) => {
+                    let key = Key::GaussianMin(self.model_store.intern(name(f)));
+                    let min = self.model_store.map_get(model_name, key);
+                    if v < min {
+                        self.model_store.map_add(model_name, key, v - min);
+                    }
+                }
+            }
+        }
+
+        for (f, c) in gaussian_std {
+            self.merge_gaussian_std(model_name, other, f, c);
+        }
+        for (f, c) in gaussian {
+            self.merge_gaussian(model_name, other, f, c);
+        }
+    }
+
+    fn merge_gaussian_std<S: ModelStore + Sync>(
+        &mut self,
+        model_name: &str,
+        other: &Model<S>,
+        feature: Id,
+        outcome: Id,
+    ) {
+        //     count = count_a + count_b
+        //     delta = mean_b - mean_a
+        //     mean = mean_a + delta * count_b / count
+        //     m2 = m2_a + m2_b + delta^2 * count_a * count_b / count
+        // both m2 started at default_gaussian_m2, which is only kept once
+        let other_stat = |stat| {
+            other
+                .model_store
+                .map_get(model_name, Key::GaussianStd(stat, feature, outcome))
+        };
+        let count_b = other_stat(GaussianStat::Count);
+        if count_b <= 0.0 {
+            return;
+        }
+        let mean_b = other_stat(GaussianStat::Mean);
+        let m2_b = other_stat(GaussianStat::M2);
+
+        let feature = self.intern_name(other, feature);
+        let outcome = self.intern_name(other, outcome);
+        let count_key = Key::GaussianStd(GaussianStat::Count, feature, outcome);
+        let mean_key = Key::GaussianStd(GaussianStat::Mean, feature, outcome);
+        let m2_key = Key::GaussianStd(GaussianStat::M2, feature, outcome);
+
+        let count_a = self.model_store.map_get(model_name, count_key);
+        let mean_a = self.model_store.map_get(model_name, mean_key);
+        let m2_a = self.model_store.map_get(model_name, m2_key);
+
+        let (mean, m2) = if count_a <= 0.0 {
+            (mean_b, m2_b)
+        } else {
+            let count = count_a + count_b;
+            let delta = mean_b - mean_a;
+            (
+                mean_a + delta * count_b / count,
+                m2_a + m2_b - self.default_gaussian_m2 + delta * delta * count_a * count_b / count,
+            )
+        };
+
+        self.model_store.map_add(model_name, count_key, count_b);
+        self.model_store
+            .map_add(model_name, mean_key, mean - mean_a);
+        self.model_store.map_add(model_name, m2_key, m2 - m2_a);
+    }
+
+    fn merge_gaussian<S: ModelStore + Sync>(
+        &mut self,
+        model_name: &str,
+        other: &Model<S>,
+        feature: Id,
+        outcome: Id,
+    ) {
+        //     count = count_a + count_b
+        //     mean = (mean_a * count_a + mean_b * count_b) / count
+        let other_stat = |stat| {
+            other
+                .model_store
+                .map_get(model_name, Key::Gaussian(stat, feature, outcome))
+        };
+        let count_b = other_stat(GaussianStat::Count);
+        if count_b <= 0.0 {
+            return;
+        }
+        let mean_b = other_stat(GaussianStat::Mean);
+
+        let feature = self.intern_name(other, feature);
+        let outcome = self.intern_name(other, outcome);
+        let count_key = Key::Gaussian(GaussianStat::Count, feature, outcome);
+        let mean_key = Key::Gaussian(GaussianStat::Mean, feature, outcome);
+
+        let count_a = self.model_store.map_get(model_name, count_key);
+        let mean_a = self.model_store.map_get(model_name, mean_key);
+
+        let count = self.model_store.map_add(model_name, count_key, count_b);
+        let mean = if count_a <= 0.0 {
+            mean_b
+        } else {
+            (mean_a * count_a + mean_b * count_b) / count
+        };
+        self.model_store
+            .map_add(model_name, mean_key, mean - mean_a);
+    }
+
+    // the id in this model of a name of `other`
+    fn intern_name<S: ModelStore + Sync>(&mut self, other: &Model<S>, id: Id) -> Id {
+        self.model_store
+            .intern(other.model_store.name(id).unwrap_or_default())
+    }
+
+    // a model with the same settings and nothing counted
+    fn empty_like(&self) -> Model<ModelHashMapStore> {
+        Model {
+            model_store: ModelHashMapStore::default(),
+            tokenizer: self.tokenizer.clone(),
+            feature_tokenizers: self.feature_tokenizers.clone(),
+            feature_ngrams: self.feature_ngrams.clone(),
+            stop_words: self.stop_words.clone(),
+            default_gaussian_m2: self.default_gaussian_m2,
+            default_gaussian_sigma_factor: self.default_gaussian_sigma_factor,
+            pseudo_count: self.pseudo_count,
+            prior_factor: self.prior_factor,
+            invalid_value_policy: self.invalid_value_policy,
+            scoring: self.scoring,
+            prior_counting: self.prior_counting,
+            log_prob_cache: RwLock::new(HashMap::new()),
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::{Error, GaussianStat, Key, Model, ModelHashMapStore, ModelStore, Result};
+
+/// Version of the file format written by `Model::save`.
+///
+/// Version 0 is a bare `serde_json` serialization of a `Model` without the versioned envelope,
+/// which is what you got when serializing a `Model` yourself before `save` existed. Version 1
+/// wraps the same model in the envelope.
+pub const FORMAT_VERSION: u64 = 2;
+
+// The envelope written to disk, the model itself holds the hyperparameters and the model store.
+#[derive(Serialize)]
+struct ModelFile<'a, T: ModelStore + Sync> {
+    format_version: u64,
+    model: &'a Model<T>,
+}
+
+impl<T: ModelStore + Sync + Serialize> Model<T> {
+    /// Save the model, including its hyperparameters and everything it has learned, to `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let f = File::create(path)?;
+        let mut f = BufWriter::new(f);
+        self.save_to_writer(&mut f)?;
+        Ok(f.flush()?)
+    }
+
+    pub fn save_to_writer<W: Write>(&self, writer: W) -> Result<()> {
+        let file = ModelFile {
+            format_version: FORMAT_VERSION,
+            model: self,
+        };
+        serde_json::to_writer(writer, &file)?;
+        Ok(())
+    }
+}
+
+impl<T: ModelStore + Sync + DeserializeOwned> Model<T> {
+    /// Load a model saved by `Model::save`. Files written by older format versions are migrated,
+    /// files written by a newer version of this crate are refused.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let f = File::open(path)?;
+        Model::load_from_reader(BufReader::new(f))
+    }
+
+    pub fn load_from_reader<R: Read>(reader: R) -> Result<Self> {
+        let file: Value = serde_json::from_reader(reader)?;
+        let model = migrate(file)?;
+        Ok(serde_json::from_value(model)?)
+    }
+}
+
+/// Bring a saved model file of any supported format version up to the current version
+/// and return the json value of the model inside it.
+fn migrate(mut file: Value) -> Result<Value> {
+    let version = match file.get("format_version") {
+        None => 0,
+        Some(v) => v
+            .as_u64()
+            .ok_or_else(|| Error::InvalidModelFile(format!("invalid format_version: {}", v)))?,
+    };
+
+    if version > FORMAT_VERSION {
+        return Err(Error::UnsupportedFormatVersion(version));
+    }
+
+    let mut model = if version == 0 {
+        file
+    } else {
+        match file.get_mut("model") {
+            Some(model) => model.take(),
+            None => return Err(Error::InvalidModelFile("no model in it".to_owned())),
+        }
+    };
+
+    let m = match model.as_object_mut() {
+        Some(m) => m,
+        None => return Err(Error::InvalidModelFile("model is not an object".to_owned())),
+    };
+
+    if version < 2 {
+        // models before version 2 counted class priors once per feature of an example
+        m.insert("prior_counting".to_owned(), json!("PerFeature"));
+
+        // they had a regex instead of a tokenizer
+        if let Some(regex) = m.remove("regex") {
+            m.insert("tokenizer".to_owned(), json!({ "Regex": regex }));
+        }
+
+        // and kept their counts under "{model}|{prefix}" strings
+        if let Some(store) = m.get_mut("model_store") {
+            if store.get("map").is_some() {
+                *store = store_from_string_keys(store)?;
+            }
+        }
+    }
+
+    Ok(model)
+}
+
+// A `ModelHashMapStore` from the "map" and "class_map" of the store before version 2, with the
+// vocabulary taken from the _Vw markers and the counts of words in all classes summed from the
+// counts in each class. The parts of a key are separated by '|', which is ambiguous for names
+// holding a '|' themselves. Model names and classes are known from the class map, so those are
+// matched first, a feature name is taken to end at the first '|'.
+fn store_from_string_keys(old: &Value) -> Result<Value> {
+    let mut store = ModelHashMapStore::default();
+    let invalid = |key: &str| Error::InvalidModelFile(format!("unknown count '{}'", key));
+
+    let class_map: HashMap<String, Vec<String>> =
+        serde_json::from_value(old.get("class_map").cloned().unwrap_or_else(|| json!({})))?;
+
+    for (model_name, classes) in &class_map {
+        for class in classes {
+            store.save_class(model_name, class);
+        }
+    }
+
+    let map: HashMap<String, f64> =
+        serde_json::from_value(old.get("map").cloned().unwrap_or_else(|| json!({})))?;
+    for (old_key, v) in map {
+        let (model_name, prefix) =
+            split_name(&old_key, class_map.keys()).ok_or_else(|| invalid(&old_key))?;
+        let classes = class_map.get(model_name).into_iter().flatten();
+
+        let (tag, rest) = prefix.split_once('|').unwrap_or((prefix, ""));
+        let feature_and =
+            |rest| split_name(rest, std::iter::empty()).ok_or_else(|| invalid(&old_key));
+        let class_and = |rest| split_name(rest, classes.clone()).ok_or_else(|| invalid(&old_key));
+        let mut id = |name: &str| store.intern(name);
+
+        let key = match tag {
+            "_Ncn" => Key::Prior(id(rest)),
+            "_N" => Key::Total,
+            "_c_f_c" => {
+                let (feature, rest) = feature_and(rest)?;
+                let (class, word) = class_and(rest)?;
+                let (feature, word) = (id(feature), id(word));
+                store.map_add(model_name, Key::WordInAllClasses(feature, word), v);
+                Key::WordInClass(feature, store.intern(class), word)
+            }
+            "_c_c" | "_Gstd_count" | "_Gstd_mean" | "_Gstd_m2" | "_G_count" | "_G_mean" => {
+                let (feature, class) = feature_and(rest)?;
+                let (feature, class) = (id(feature), id(class));
+                match tag {
+                    "_c_c" => {
+                        store.map_add(model_name, Key::AllClassesTotal(feature), v);
+                        Key::ClassTotal(feature, class)
+                    }
+                    "_Gstd_count" => Key::GaussianStd(GaussianStat::Count, feature, class),
+                    "_Gstd_mean" => Key::GaussianStd(GaussianStat::Mean, feature, class),
+                    "_Gstd_m2" => Key::GaussianStd(GaussianStat::M2, feature, class),
+                    "_G_count" => Key::Gaussian(GaussianStat::Count, feature, class),
+                    _ => Key::Gaussian(GaussianStat::Mean, feature, class),
+                }
+            }
+            "_Vw" => {
+                let (feature_name, word_name) = feature_and(rest)?;
+                if v != 0.0 {
+                    store.save_word(model_name, feature_name, word_name);
+                }
+                Key::VocabMarker(store.intern(feature_name), store.intern(word_name))
+            }
+            "_V" => Key::VocabSize(id(rest)),
+            "_G_max" => Key::GaussianMax(id(rest)),
+            "_G_min" => Key::GaussianMin(id(rest)),
+            _ => return Err(invalid(&old_key)),
+        };
+        store.map_add(model_name, key, v);
+    }
+
+    Ok(serde_json::to_value(store)?)
+}
+
+// "{name}|{rest}" split at the longest of the known names it starts with, at the first '|' otherwise
+fn split_name<'a, 'b, I: Iterator<Item = &'b String>>(
+    s: &'a str,
+    known: I,
+) -> Option<(&'a str, &'a str)> {
+    known
+        .filter(|n| s.len() > n.len() && s.starts_with(n.as_str()) && s.as_bytes()[n.len()] == b'|')
+        .map(|n| n.len())
+        .max()
+        .or_else(|| s.find('|'))
+        .map(|i| (&s[..i], &s[i + 1..]))
+}
+
+#[test]
+fn migrate_refuses_newer_versions() {
+    let file = json!({ "format_version": FORMAT_VERSION + 1, "model": {} });
+    match migrate(file) {
+        Err(Error::UnsupportedFormatVersion(v)) => assert_eq!(FORMAT_VERSION + 1, v),
+        _ => panic!("expected UnsupportedFormatVersion"),
+    }
+
+    let file = json!({ "format_version": "one", "model": {} });
+    assert!(migrate(file).is_err());
+}
+
+#[test]
+fn migrate_unwraps_envelope() {
+    let file = json!({ "format_version": FORMAT_VERSION, "model": { "pseudo_count": 1.0 } });
+    assert_eq!(json!({ "pseudo_count": 1.0 }), migrate(file).unwrap());
+
+    // version 0, a bare model without envelope
+    let file = json!({ "pseudo_count": 1.0 });
+    assert_eq!(
+        json!({ "pseudo_count": 1.0, "prior_counting": "PerFeature" }),
+        migrate(file).unwrap()
+    );
+}
+
+#[test]
+fn migrate_turns_regex_into_tokenizer_before_version_2() {
+    let file = json!({ "format_version": 1, "model": { "regex": "[^a-z]+" } });
+    assert_eq!(
+        json!({ "tokenizer": { "Regex": "[^a-z]+" }, "prior_counting": "PerFeature" }),
+        migrate(file).unwrap()
+    );
+}
+
+#[test]
+fn migrate_flags_per_feature_priors_before_version_2() {
+    let file = json!({ "format_version": 1, "model": { "pseudo_count": 1.0 } });
+    assert_eq!(
+        json!({ "pseudo_count": 1.0, "prior_counting": "PerFeature" }),
+        migrate(file).unwrap()
+    );
+
+    let file = json!({ "format_version": 2, "model": { "prior_counting": "PerExample" } });
+    assert_eq!(
+        json!({ "prior_counting": "PerExample" }),
+        migrate(file).unwrap()
+    );
+}
+
+#[test]
+fn migrate_turns_string_keys_into_keys_before_version_2() {
+    let file = json!({ "format_version": 1, "model": { "model_store": { "map": {
+        "m|_N": 3.0,
+        "m|_Ncn|a|b": 2.0,
+        "m|_Ncn|c": 1.0,
+        "m|_c_f_c|text|a|b|hello": 2.0,
+        "m|_c_f_c|text|c|hello": 3.0,
+        "m|_c_c|text|a|b": 2.0,
+        "m|_c_c|text|c": 4.0,
+        "m|_Vw|text|hello": 1.0,
+        "m|_Vw|text|gone": 0.0,
+        "m|_V|text": 1.0,
+        "m|_Gstd_m2|age|c": 0.5,
+        "m|_G_max|height": 180.0,
+    }, "class_map": { "m": ["a|b", "c"] } } } });
+    let store: ModelHashMapStore =
+        serde_json::from_value(migrate(file).unwrap()["model_store"].take()).unwrap();
+
+    let id = |name: &str| store.lookup(name).unwrap();
+    let (text, age, height, hello) = (id("text"), id("age"), id("height"), id("hello"));
+    let (a_b, c) = (id("a|b"), id("c"));
+    assert_eq!(3.0, store.map_get("m", Key::Total));
+    assert_eq!(2.0, store.map_get("m", Key::Prior(a_b)));
+    assert_eq!(1.0, store.map_get("m", Key::Prior(c)));
+    assert_eq!(2.0, store.map_get("m", Key::WordInClass(text, a_b, hello)));
+    assert_eq!(2.0, store.map_get("m", Key::ClassTotal(text, a_b)));
+    assert_eq!(5.0, store.map_get("m", Key::WordInAllClasses(text, hello)));
+    assert_eq!(6.0, store.map_get("m", Key::AllClassesTotal(text)));
+    assert_eq!(1.0, store.map_get("m", Key::VocabMarker(text, hello)));
+    assert_eq!(1.0, store.map_get("m", Key::VocabSize(text)));
+    assert_eq!(
+        0.5,
+        store.map_get("m", Key::GaussianStd(GaussianStat::M2, age, c))
+    );
+    assert_eq!(180.0, store.map_get("m", Key::GaussianMax(height)));
+    assert_eq!(2, store.get_all_classes("m").unwrap().len());
+    assert_eq!(
+        Some(vec!["hello".to_owned()].into_iter().collect()),
+        store.get_all_words("m", "text")
+    );
+
+    let file = json!({ "format_version": 1, "model": { "model_store": {
+        "map": { "m|_Unknown|x": 1.0 }, "class_map": {} } } });
+    assert!(migrate(file).is_err());
+}
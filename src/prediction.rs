@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+
+/// The prediction of one example, with the log scores behind the probabilities.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Prediction {
+    /// The log joint score of each class, prior_factor * log(p(c_n)) + sum_i log(p(x_i | c_n)).
+    /// Words that no class has seen are left out, so the scores are comparable among classes
+    /// but not a probability of the example itself.
+    pub log_scores: HashMap<String, f64>,
+
+    /// log of the sum of exp of the log scores of all classes, the normalizer of the probabilities.
+    pub log_evidence: f64,
+
+    /// The probability of each class, exp(log score - log evidence).
+    pub probabilities: HashMap<String, f64>,
+}
+
+impl Prediction {
+    pub(crate) fn from_log_scores(log_scores: HashMap<String, f64>) -> Self {
+        // summed in the order of the classes, so the same scores always give the same evidence
+        let mut scores: Vec<(&String, &f64)> = log_scores.iter().collect();
+        scores.sort_by_key(|(class, _)| *class);
+        let log_evidence = log_sum_exp(scores.iter().map(|(_, score)| **score));
+        let probabilities = log_scores
+            .iter()
+            .map(|(class, score)| (class.to_owned(), (score - log_evidence).exp()))
+            .collect();
+
+        Prediction {
+            log_scores,
+            log_evidence,
+            probabilities,
+        }
+    }
+}
+
+/// log(sum(exp(v))), taking out the max first so that exp does not overflow or underflow to 0.
+fn log_sum_exp<I: Iterator<Item = f64> + Clone>(values: I) -> f64 {
+    let max = values.clone().fold(f64::NEG_INFINITY, f64::max);
+    if max.is_infinite() {
+        return max;
+    }
+
+    max + values.map(|v| (v - max).exp()).sum::<f64>().ln()
+}
+
+#[test]
+fn normalize_works() {
+    let mut map = HashMap::new();
+    map.insert("a".to_owned(), 1.0);
+    map.insert("b".to_owned(), 5.0);
+
+    let map = Prediction::from_log_scores(map).probabilities;
+    assert!((0.017986209962091555 - *map.get("a").unwrap()).abs() < 1e-12);
+    assert!((0.9820137900379085 - *map.get("b").unwrap()).abs() < 1e-12);
+
+    // Noticing an interesting character for normalize:
+    // If you add same value onto the original values, the normalized result won't change.
+    // We utilize this character on prediction to allow log probability calculated also for those
+    // unknown words
+    let mut map = HashMap::new();
+    map.insert("a".to_owned(), 1.0 + 500.0);
+    map.insert("b".to_owned(), 5.0 + 500.0);
+
+    let map = Prediction::from_log_scores(map).probabilities;
+    assert!((0.017986209962091555 - *map.get("a").unwrap()).abs() < 1e-12);
+    assert!((0.9820137900379085 - *map.get("b").unwrap()).abs() < 1e-12);
+}
+
+#[test]
+fn normalize_does_not_panic_on_nan() {
+    let mut map = HashMap::new();
+    map.insert("a".to_owned(), f64::NAN);
+    map.insert("b".to_owned(), 5.0);
+
+    let map = Prediction::from_log_scores(map).probabilities;
+    assert!(map.get("a").unwrap().is_nan());
+}
+
+#[test]
+fn log_sum_exp_works() {
+    assert_eq!(f64::NEG_INFINITY, log_sum_exp(vec![].into_iter()));
+    assert!((log_sum_exp(vec![1.0, 5.0].into_iter()) - 5.018149927917809).abs() < 1e-12);
+
+    // scores of long texts, exp of them underflows to 0
+    let lse = log_sum_exp(vec![-2000.0, -2000.0].into_iter());
+    assert!((lse - (-2000.0 + 2f64.ln())).abs() < 1e-9);
+}
@@ -0,0 +1,46 @@
+// A small seeded random number generator (SplitMix64), enough for shuffling data reproducibly
+// without depending on a random crate.
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in 0..n
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// Fisher-Yates shuffle
+    pub(crate) fn shuffle<T>(&mut self, v: &mut [T]) {
+        for i in (1..v.len()).rev() {
+            v.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+#[test]
+fn rng_works() {
+    let mut a = Rng::new(42);
+    let mut b = Rng::new(42);
+    let mut v: Vec<usize> = (0..10).collect();
+    let mut w = v.clone();
+    a.shuffle(&mut v);
+    b.shuffle(&mut w);
+    assert_eq!(v, w);
+    assert_ne!((0..10).collect::<Vec<_>>(), v);
+
+    v.sort();
+    assert_eq!((0..10).collect::<Vec<_>>(), v);
+}
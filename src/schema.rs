@@ -0,0 +1,348 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use crate::{Error, Feature, FeatureType, Result};
+
+/// How the columns of a CSV file turn into the features and the label of an example.
+///
+/// A schema is usually loaded from a TOML or JSON file, for example:
+///
+/// ```toml
+/// label = "income"
+/// missing_values = ["?"]
+/// comment = "|"
+///
+/// [label_normalization]
+/// strip_suffix = "."
+///
+/// [[columns]]
+/// name = "age"
+/// type = "Gaussian"
+///
+/// [[columns]]
+/// name = "workclass"
+/// type = "Category"
+///
+/// [[columns]]
+/// name = "income"
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Schema {
+    /// The columns in the order of the file.
+    pub columns: Vec<Column>,
+
+    /// Name of the column holding the label, `None` for data without labels.
+    #[serde(default)]
+    pub label: Option<String>,
+
+    /// Values taken as missing, the feature is left out of the example. An empty value
+    /// is always missing. A missing label is an error, as the example can not be trained.
+    #[serde(default)]
+    pub missing_values: Vec<String>,
+
+    #[serde(default)]
+    pub label_normalization: LabelNormalization,
+
+    /// Whether the first line of the file holds the column names, it is skipped then.
+    #[serde(default)]
+    pub has_headers: bool,
+
+    /// Field delimiter, a comma if not set.
+    #[serde(default)]
+    pub delimiter: Option<char>,
+
+    /// Lines starting with this character are skipped.
+    #[serde(default)]
+    pub comment: Option<char>,
+}
+
+/// A column of a CSV file. A column without a feature type, such as the label column,
+/// is not a feature.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Column {
+    pub name: String,
+
+    #[serde(default, rename = "type")]
+    pub feature_type: Option<FeatureType>,
+}
+
+/// Changes made to each label as it is read, the suffix is stripped before lowercasing.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LabelNormalization {
+    /// Remove this from the end of a label, e.g. "." for labels written like "<=50K.".
+    #[serde(default)]
+    pub strip_suffix: Option<String>,
+
+    #[serde(default)]
+    pub lowercase: bool,
+}
+
+impl LabelNormalization {
+    pub fn apply(&self, label: &str) -> String {
+        let label = match &self.strip_suffix {
+            Some(suffix) => label.strip_suffix(suffix.as_str()).unwrap_or(label),
+            None => label,
+        };
+        if self.lowercase {
+            label.to_lowercase()
+        } else {
+            label.to_owned()
+        }
+    }
+}
+
+impl Schema {
+    /// Load a schema from a file, TOML if the file name ends with ".toml", JSON otherwise.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        if path.extension().is_some_and(|e| e == "toml") {
+            Schema::from_toml(&text)
+        } else {
+            Schema::from_json(&text)
+        }
+    }
+
+    pub fn from_json(text: &str) -> Result<Self> {
+        serde_json::from_str::<Schema>(text)
+            .map_err(|e| Error::InvalidSchema(e.to_string()))?
+            .checked()
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self> {
+        toml::from_str::<Schema>(text)
+            .map_err(|e| Error::InvalidSchema(e.to_string()))?
+            .checked()
+    }
+
+    fn checked(self) -> Result<Self> {
+        for c in self.delimiter.iter().chain(self.comment.iter()) {
+            if !c.is_ascii() {
+                return Err(Error::InvalidSchema(format!(
+                    "delimiter and comment must be ASCII characters, got '{}'",
+                    c
+                )));
+            }
+        }
+        if let Some(label) = &self.label {
+            if !self.columns.iter().any(|c| &c.name == label) {
+                return Err(Error::InvalidSchema(format!(
+                    "label column '{}' is not one of the columns",
+                    label
+                )));
+            }
+        }
+        Ok(self)
+    }
+
+    fn is_missing(&self, value: &str) -> bool {
+        value.is_empty() || self.missing_values.iter().any(|m| m == value)
+    }
+
+    /// Turn the fields of a row into a label and features, `line` is the line of the row for
+    /// errors. The label is empty if the schema has no label column, a row without a label
+    /// is an `Error::Parse` otherwise, as is a row with more or fewer fields than columns.
+    fn example<'a, I: Iterator<Item = &'a str>>(
+        &self,
+        line: usize,
+        mut fields: I,
+    ) -> Result<(String, Vec<Feature>)> {
+        let mut label = None;
+        let mut features = vec![];
+        let mut count = 0;
+
+        for (column, value) in self.columns.iter().zip(fields.by_ref()) {
+            count += 1;
+            if self.label.as_ref() == Some(&column.name) {
+                label = Some(value)
+                    .filter(|v| !self.is_missing(v))
+                    .map(|v| self.label_normalization.apply(v))
+                    .filter(|l| !l.is_empty());
+            } else if let Some(feature_type) = &column.feature_type {
+                if !self.is_missing(value) {
+                    features.push(Feature {
+                        feature_type: *feature_type,
+                        name: column.name.to_owned(),
+                        value: value.to_owned(),
+                    });
+                }
+            }
+        }
+
+        let count = count + fields.count();
+        if count != self.columns.len() {
+            return Err(Error::Parse {
+                line,
+                message: format!(
+                    "{} fields for the {} columns of the schema",
+                    count,
+                    self.columns.len()
+                ),
+            });
+        }
+
+        match (&self.label, label) {
+            (None, _) => Ok((String::new(), features)),
+            (Some(_), Some(label)) => Ok((label, features)),
+            (Some(column), None) => Err(Error::Parse {
+                line,
+                message: format!("no label in column '{}'", column),
+            }),
+        }
+    }
+}
+
+/// Reads the rows of a CSV file one by one as `(label, features)` by a `Schema`, without
+/// loading the whole file. Fields are trimmed of whitespace.
+///
+/// ```no_run
+/// use rust_nb::{CsvReader, Model, Schema};
+///
+/// let schema = Schema::load("schema.toml").unwrap();
+/// let mut reader = CsvReader::from_path(schema, "train.csv").unwrap();
+/// let mut model = Model::new();
+/// loop {
+///     let batch = reader.next_batch(10_000).unwrap();
+///     if batch.is_empty() {
+///         break;
+///     }
+///     model.train("my_model", &batch);
+/// }
+/// ```
+pub struct CsvReader<R: Read> {
+    schema: Schema,
+    reader: csv::Reader<R>,
+    record: csv::StringRecord,
+}
+
+impl CsvReader<File> {
+    pub fn from_path<P: AsRef<Path>>(schema: Schema, path: P) -> Result<Self> {
+        Ok(CsvReader::from_reader(schema, File::open(path)?))
+    }
+}
+
+impl<R: Read> CsvReader<R> {
+    pub fn from_reader(schema: Schema, reader: R) -> Self {
+        let reader = csv::ReaderBuilder::new()
+            .has_headers(schema.has_headers)
+            // the number of fields is checked against the schema
+            .flexible(true)
+            .delimiter(schema.delimiter.unwrap_or(',') as u8)
+            .comment(schema.comment.map(|c| c as u8))
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+
+        CsvReader {
+            schema,
+            reader,
+            record: csv::StringRecord::new(),
+        }
+    }
+
+    /// The next examples, up to `size` of them, none at the end of the file.
+    pub fn next_batch(&mut self, size: usize) -> Result<Vec<(String, Vec<Feature>)>> {
+        self.take(size).collect()
+    }
+}
+
+impl<R: Read> Iterator for CsvReader<R> {
+    type Item = Result<(String, Vec<Feature>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.reader.read_record(&mut self.record) {
+            Ok(true) => {
+                let line = self.record.position().map_or(0, |p| p.line() as usize);
+                Some(self.schema.example(line, self.record.iter()))
+            }
+            Ok(false) => None,
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
+
+#[test]
+fn schema_works() {
+    let schema = Schema::from_toml(
+        r#"
+        label = "income"
+        missing_values = ["?"]
+
+        [label_normalization]
+        strip_suffix = "."
+
+        [[columns]]
+        name = "age"
+        type = "Gaussian"
+
+        [[columns]]
+        name = "id"
+
+        [[columns]]
+        name = "income"
+        "#,
+    )
+    .unwrap();
+
+    let (label, features) = schema
+        .example(1, vec!["39", "7", "<=50K."].into_iter())
+        .unwrap();
+    assert_eq!("<=50K", label);
+    assert_eq!(1, features.len());
+    assert_eq!("age", features[0].name);
+
+    let (_, features) = schema
+        .example(2, vec!["?", "7", ">50K"].into_iter())
+        .unwrap();
+    assert!(features.is_empty());
+
+    // missing labels, a short and a long row and a label that is only the stripped suffix
+    for (line, fields) in vec![
+        vec!["39", "7", "?"],
+        vec!["39", "7", ""],
+        vec!["39", "7"],
+        vec!["39", "7", ">50K", "x"],
+        vec!["39", "7", "."],
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        match schema.example(line, fields.into_iter()) {
+            Err(Error::Parse { line: l, .. }) => assert_eq!(line, l),
+            _ => panic!("expected a parse error"),
+        }
+    }
+
+    let json = serde_json::to_string(&schema).unwrap();
+    assert_eq!(schema, Schema::from_json(&json).unwrap());
+
+    assert!(Schema::from_json(r#"{"columns": [{"name": "a"}], "label": "b"}"#).is_err());
+}
+
+#[test]
+fn csv_reader_works() {
+    let schema = Schema::from_json(
+        r#"{"columns": [{"name": "text", "type": "Text"}, {"name": "label"}],
+            "label": "label", "has_headers": true, "comment": "|"}"#,
+    )
+    .unwrap();
+    let data = "text,label\n|a comment\nhello there, spam\n\"a, b\",ham\nno label,\nx,y,z\n\n";
+
+    let mut reader = CsvReader::from_reader(schema, data.as_bytes());
+    let batch = reader.next_batch(2).unwrap();
+    assert_eq!("spam", batch[0].0);
+    assert_eq!("hello there", batch[0].1[0].value);
+    assert_eq!("a, b", batch[1].1[0].value);
+
+    match reader.next().unwrap() {
+        Err(Error::Parse { line, .. }) => assert_eq!(5, line),
+        _ => panic!("expected a parse error"),
+    }
+
+    // a row with more fields than columns
+    match reader.next().unwrap() {
+        Err(Error::Parse { line, .. }) => assert_eq!(6, line),
+        _ => panic!("expected a parse error"),
+    }
+    assert!(reader.next().is_none());
+}
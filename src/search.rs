@@ -0,0 +1,295 @@
+use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::eval::{stratified_folds, try_evaluate, CrossValidation};
+use crate::random::Rng;
+use crate::{Feature, Model, ModelStore, Result};
+
+/// Hyperparameters of a model to try, those that are `None` keep the value the builder gives.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Settings {
+    pub pseudo_count: Option<f64>,
+    pub prior_factor: Option<f64>,
+    pub default_gaussian_sigma_factor: Option<f64>,
+    pub default_gaussian_m2: Option<f64>,
+}
+
+impl Settings {
+    pub fn apply<T: ModelStore + Sync>(&self, mut model: Model<T>) -> Model<T> {
+        if let Some(pseudo_count) = self.pseudo_count {
+            model = model.with_pseudo_count(pseudo_count);
+        }
+        if let Some(prior_factor) = self.prior_factor {
+            model = model.with_prior_factor(prior_factor);
+        }
+        if let Some(sigma_factor) = self.default_gaussian_sigma_factor {
+            model = model.with_default_gaussian_sigma_factor(sigma_factor);
+        }
+        if let Some(m2) = self.default_gaussian_m2 {
+            model = model.with_default_gaussian_m2(m2);
+        }
+        model
+    }
+}
+
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let values = [
+            ("pseudo_count", self.pseudo_count),
+            ("prior_factor", self.prior_factor),
+            ("sigma_factor", self.default_gaussian_sigma_factor),
+            ("m2", self.default_gaussian_m2),
+        ];
+        let set: Vec<String> = values
+            .iter()
+            .filter_map(|(name, v)| v.map(|v| format!("{}={}", name, v)))
+            .collect();
+        if set.is_empty() {
+            write!(f, "defaults")
+        } else {
+            write!(f, "{}", set.join(" "))
+        }
+    }
+}
+
+/// The values to try of each hyperparameter, a hyperparameter without values is not changed.
+///
+/// ```
+/// use rust_nb::search::Grid;
+///
+/// let grid = Grid::new()
+///     .with_pseudo_count(&[0.1, 0.5, 1.0])
+///     .with_prior_factor(&[0.5, 1.0]);
+/// assert_eq!(6, grid.settings().len());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Grid {
+    pub pseudo_count: Vec<f64>,
+    pub prior_factor: Vec<f64>,
+    pub default_gaussian_sigma_factor: Vec<f64>,
+    pub default_gaussian_m2: Vec<f64>,
+}
+
+impl Grid {
+    pub fn new() -> Self {
+        Grid::default()
+    }
+
+    pub fn with_pseudo_count(mut self, values: &[f64]) -> Self {
+        self.pseudo_count = values.to_vec();
+        self
+    }
+
+    pub fn with_prior_factor(mut self, values: &[f64]) -> Self {
+        self.prior_factor = values.to_vec();
+        self
+    }
+
+    pub fn with_default_gaussian_sigma_factor(mut self, values: &[f64]) -> Self {
+        self.default_gaussian_sigma_factor = values.to_vec();
+        self
+    }
+
+    pub fn with_default_gaussian_m2(mut self, values: &[f64]) -> Self {
+        self.default_gaussian_m2 = values.to_vec();
+        self
+    }
+
+    /// All combinations of the values, for a grid search.
+    pub fn settings(&self) -> Vec<Settings> {
+        let mut all = vec![Settings::default()];
+        all = combine(all, &self.pseudo_count, |s, v| s.pseudo_count = Some(v));
+        all = combine(all, &self.prior_factor, |s, v| s.prior_factor = Some(v));
+        all = combine(all, &self.default_gaussian_sigma_factor, |s, v| {
+            s.default_gaussian_sigma_factor = Some(v)
+        });
+        combine(all, &self.default_gaussian_m2, |s, v| {
+            s.default_gaussian_m2 = Some(v)
+        })
+    }
+
+    /// `n` different combinations of the values picked at random with `seed`, for a random search.
+    pub fn sample(&self, n: usize, seed: u64) -> Vec<Settings> {
+        let mut all = self.settings();
+        Rng::new(seed).shuffle(&mut all);
+        all.truncate(n);
+        all
+    }
+}
+
+fn combine<F: Fn(&mut Settings, f64)>(all: Vec<Settings>, values: &[f64], set: F) -> Vec<Settings> {
+    if values.is_empty() {
+        return all;
+    }
+
+    let set = &set;
+    all.into_iter()
+        .flat_map(|settings| {
+            values.iter().map(move |v| {
+                let mut settings = settings;
+                set(&mut settings, *v);
+                settings
+            })
+        })
+        .collect()
+}
+
+/// The cross validation of one of the settings of a search.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchRow {
+    pub settings: Settings,
+    pub cross_validation: CrossValidation,
+}
+
+/// The outcome of `search`.
+pub struct SearchResult<T: ModelStore + Sync> {
+    /// All settings tried, the best first.
+    pub ranking: Vec<SearchRow>,
+
+    /// A model with the best settings, trained on all data under the model name given.
+    pub best_model: Model<T>,
+}
+
+impl<T: ModelStore + Sync> SearchResult<T> {
+    pub fn best_settings(&self) -> Settings {
+        self.ranking[0].settings
+    }
+}
+
+impl<T: ModelStore + Sync> fmt::Display for SearchResult<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>4} {:>9} {:>9} {:>9} {:>9}  settings",
+            "rank", "accuracy", "std", "macro f1", "log loss"
+        )?;
+        for (i, row) in self.ranking.iter().enumerate() {
+            let cv = &row.cross_validation;
+            writeln!(
+                f,
+                "{:>4} {:>9.4} {:>9.4} {:>9.4} {:>9.4}  {}",
+                i + 1,
+                cv.accuracy,
+                cv.accuracy_std,
+                cv.macro_f1,
+                cv.log_loss,
+                row.settings
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Cross validate a model from `builder` with each of the `settings` on the same `k` folds
+/// of `data`, see `eval::cross_validate`. All folds of all settings are trained and evaluated
+/// in parallel. The settings are ranked by mean accuracy, then by mean log loss, and a model with
+/// the best settings is trained on all of `data` as `model_name`.
+/// Panics where `try_search` would return an error, if `settings` is empty, or if `k` is less
+/// than 2 or more than the number of examples.
+///
+/// ```no_run
+/// use rust_nb::search::{self, Grid};
+/// use rust_nb::Model;
+///
+/// # let data = vec![];
+/// let grid = Grid::new().with_pseudo_count(&[0.1, 1.0]);
+/// let result = search::search("spam", Model::new, &grid.settings(), &data, 5, 42);
+/// println!("{}", result);
+/// ```
+pub fn search<T, F>(
+    model_name: &str,
+    builder: F,
+    settings: &[Settings],
+    data: &[(String, Vec<Feature>)],
+    k: usize,
+    seed: u64,
+) -> SearchResult<T>
+where
+    T: ModelStore + Sync,
+    F: Fn() -> Model<T> + Sync,
+{
+    try_search(model_name, builder, settings, data, k, seed).unwrap_or_else(|e| panic!("{}", e))
+}
+
+pub fn try_search<T, F>(
+    model_name: &str,
+    builder: F,
+    settings: &[Settings],
+    data: &[(String, Vec<Feature>)],
+    k: usize,
+    seed: u64,
+) -> Result<SearchResult<T>>
+where
+    T: ModelStore + Sync,
+    F: Fn() -> Model<T> + Sync,
+{
+    assert!(!settings.is_empty(), "no settings to search");
+
+    let folds = stratified_folds(data, k, seed);
+
+    let evaluations = settings
+        .par_iter()
+        .flat_map(|s| folds.par_iter().map(move |fold| (s, fold)))
+        .map(|(s, (train_data, test_data))| {
+            let mut model = s.apply(builder());
+            model.try_train(model_name, train_data)?;
+            try_evaluate(&model, model_name, test_data)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut ranking: Vec<SearchRow> = settings
+        .iter()
+        .zip(evaluations.chunks(k))
+        .map(|(s, folds)| SearchRow {
+            settings: *s,
+            cross_validation: CrossValidation::new(folds.to_vec()),
+        })
+        .collect();
+
+    // a stable sort, settings that score the same stay in the order given
+    ranking.sort_by(|a, b| {
+        let (a, b) = (&a.cross_validation, &b.cross_validation);
+        b.accuracy
+            .partial_cmp(&a.accuracy)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| {
+                a.log_loss
+                    .partial_cmp(&b.log_loss)
+                    .unwrap_or(Ordering::Equal)
+            })
+    });
+
+    let mut best_model = ranking[0].settings.apply(builder());
+    best_model.try_train(model_name, data)?;
+
+    Ok(SearchResult {
+        ranking,
+        best_model,
+    })
+}
+
+#[test]
+fn grid_works() {
+    let grid = Grid::new()
+        .with_pseudo_count(&[0.1, 1.0])
+        .with_default_gaussian_m2(&[2.0, 3.0, 4.0]);
+    let settings = grid.settings();
+    assert_eq!(6, settings.len());
+    assert_eq!(
+        Settings {
+            pseudo_count: Some(0.1),
+            prior_factor: None,
+            default_gaussian_sigma_factor: None,
+            default_gaussian_m2: Some(3.0),
+        },
+        settings[1]
+    );
+
+    assert_eq!(vec![Settings::default()], Grid::new().settings());
+
+    let sample = grid.sample(4, 1);
+    assert_eq!(4, sample.len());
+    assert!(sample.iter().all(|s| settings.contains(s)));
+    assert_eq!(sample, grid.sample(4, 1));
+}
@@ -0,0 +1,331 @@
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tiny_http::{Header, Method, Request, Response};
+
+use crate::{Error, Feature, Model, ModelStore, Result, SharedModel};
+
+/// An HTTP server around a model, for predicting and training online. Requests and responses
+/// are JSON, features are written as in `JsonlReader`:
+///
+/// - `POST /models/{name}/predict` with `{"features": [...]}`
+/// - `POST /models/{name}/predict_batch` with `{"examples": [{"features": [...]}, ...]}`
+/// - `POST /models/{name}/train` with `{"examples": [{"label": "spam", "features": [...]}, ...]}`
+/// - `POST /models/{name}/untrain` with a body as `train`
+/// - `GET /models/{name}/classes`, the classes with their prior counts
+/// - `GET /models/{name}/stats`, the number of classes and examples and the vocabulary sizes
+///
+/// A prediction is answered as `{"label": "spam", "probabilities": {"spam": 0.9, "ham": 0.1}}`.
+/// Predictions are served concurrently, also while training, see `SharedModel`.
+/// Errors are answered as `{"error": "..."}` with a 4xx or 5xx status.
+///
+/// ```no_run
+/// use rust_nb::server::Server;
+/// use rust_nb::Model;
+/// use std::time::Duration;
+///
+/// Server::bind("127.0.0.1:8080", Model::new())
+///     .unwrap()
+///     .with_snapshots("model.json", Duration::from_secs(60))
+///     .run()
+///     .unwrap();
+/// ```
+pub struct Server<T: ModelStore + Sync> {
+    http: tiny_http::Server,
+    model: Arc<SharedModel<T>>,
+    threads: usize,
+    snapshots: Option<(PathBuf, Duration)>,
+    snapshot_errors: Option<SnapshotErrors>,
+}
+
+// What is called with a snapshot that failed.
+type SnapshotErrors = Box<dyn Fn(&Path, &Error) + Send>;
+
+// What the worker threads share.
+struct Shared<T: ModelStore + Sync> {
+    http: tiny_http::Server,
+    model: Arc<SharedModel<T>>,
+    changed: AtomicBool,
+}
+
+#[derive(Deserialize)]
+struct PredictBody {
+    features: Vec<Feature>,
+}
+
+#[derive(Deserialize)]
+struct PredictBatchBody {
+    examples: Vec<PredictBody>,
+}
+
+#[derive(Deserialize)]
+struct TrainBody {
+    examples: Vec<Example>,
+}
+
+#[derive(Deserialize)]
+struct Example {
+    label: String,
+    features: Vec<Feature>,
+}
+
+// An error answered to a request.
+struct HttpError {
+    status: u16,
+    message: String,
+}
+
+impl HttpError {
+    fn new(status: u16, message: String) -> Self {
+        HttpError { status, message }
+    }
+}
+
+impl From<Error> for HttpError {
+    fn from(e: Error) -> Self {
+        let status = match e {
+            Error::Json(_)
+            | Error::InvalidValue { .. }
+            | Error::InvalidWeight { .. }
+            | Error::UnknownClass { .. } => 400,
+            _ => 500,
+        };
+        HttpError::new(status, e.to_string())
+    }
+}
+
+impl<T: ModelStore + Sync + Send + Clone + Serialize + 'static> Server<T> {
+    /// Listen on `addr`, e.g. "127.0.0.1:8080", or port 0 to take any free port.
+    /// Nothing is served until `run`.
+    pub fn bind(addr: &str, model: Model<T>) -> Result<Self> {
+        let http = tiny_http::Server::http(addr).map_err(|e| Error::Io(io::Error::other(e)))?;
+
+        Ok(Server {
+            http,
+            model: Arc::new(SharedModel::new(model)),
+            threads: 4,
+            snapshots: None,
+            snapshot_errors: None,
+        })
+    }
+
+    /// Number of threads serving requests, 4 by default.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        assert!(threads > 0, "a server needs at least one thread");
+        self.threads = threads;
+        self
+    }
+
+    /// Save the model to `path` with `Model::save` every `interval`, when it has been trained
+    /// since the last save. The model is serialized in memory and written after, so requests
+    /// are not held up by the disk. The file is written aside and renamed into place, so `path`
+    /// always holds a complete model. A snapshot that failed is tried again at the next
+    /// interval, the error is printed to stderr unless `with_snapshot_errors` is given.
+    pub fn with_snapshots<P: AsRef<Path>>(mut self, path: P, interval: Duration) -> Self {
+        self.snapshots = Some((path.as_ref().to_owned(), interval));
+        self
+    }
+
+    /// Call `f` with the path and the error of each snapshot that failed, instead of printing
+    /// the error to stderr.
+    pub fn with_snapshot_errors<F: Fn(&Path, &Error) + Send + 'static>(mut self, f: F) -> Self {
+        self.snapshot_errors = Some(Box::new(f));
+        self
+    }
+
+    /// The address listened on, with the port taken when binding to port 0.
+    pub fn addr(&self) -> SocketAddr {
+        self.http
+            .server_addr()
+            .to_ip()
+            .expect("server listens on an IP address")
+    }
+
+    /// The model served, to read or train it besides the requests.
+    pub fn model(&self) -> Arc<SharedModel<T>> {
+        Arc::clone(&self.model)
+    }
+
+    /// Serve requests until listening fails, which does not happen in normal operation.
+    pub fn run(self) -> Result<()> {
+        let shared = Arc::new(Shared {
+            http: self.http,
+            model: self.model,
+            changed: AtomicBool::new(false),
+        });
+
+        if let Some((path, interval)) = self.snapshots {
+            let shared = Arc::clone(&shared);
+            let errors = self.snapshot_errors.unwrap_or_else(|| {
+                Box::new(|path, e| eprintln!("snapshot to {} failed: {}", path.display(), e))
+            });
+            thread::spawn(move || loop {
+                thread::sleep(interval);
+                if shared.changed.swap(false, Ordering::SeqCst) {
+                    if let Err(e) = snapshot(&shared.model, &path) {
+                        errors(&path, &e);
+                        shared.changed.store(true, Ordering::SeqCst);
+                    }
+                }
+            });
+        }
+
+        let workers: Vec<_> = (0..self.threads)
+            .map(|_| {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || -> Result<()> {
+                    loop {
+                        let request = shared.http.recv()?;
+                        handle(&shared, request);
+                    }
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            worker.join().expect("server thread panicked")?;
+        }
+        Ok(())
+    }
+}
+
+fn snapshot<T: ModelStore + Sync + Serialize>(model: &SharedModel<T>, path: &Path) -> Result<()> {
+    // the model is only held while serializing, not while writing to disk
+    let mut bytes = vec![];
+    model.read().save_to_writer(&mut bytes)?;
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, bytes)?;
+    Ok(fs::rename(&tmp, path)?)
+}
+
+fn handle<T: ModelStore + Sync + Clone>(shared: &Shared<T>, mut request: Request) {
+    let (status, body) = match route(shared, &mut request) {
+        Ok(body) => (200, body),
+        Err(e) => (e.status, json!({ "error": e.message })),
+    };
+
+    let header = Header::from_bytes("Content-Type", "application/json").unwrap();
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(header);
+    // the client may have gone already, nothing to do about it
+    let _ = request.respond(response);
+}
+
+fn route<T: ModelStore + Sync + Clone>(
+    shared: &Shared<T>,
+    request: &mut Request,
+) -> std::result::Result<Value, HttpError> {
+    let url = request.url().to_owned();
+    let path = url.split('?').next().unwrap_or_default();
+    let parts: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    let (model_name, action) = match parts.as_slice() {
+        ["models", model_name, action] if !model_name.is_empty() => (*model_name, *action),
+        _ => return Err(HttpError::new(404, format!("no such path {}", path))),
+    };
+
+    let method = request.method().clone();
+    match (method, action) {
+        (Method::Post, "predict") => {
+            let body: PredictBody = read_json(request)?;
+            let model = shared.model.read();
+            known_model(&model, model_name)?;
+            let ranked = model.try_predict_top_k(model_name, &body.features, usize::MAX)?;
+            Ok(prediction(ranked))
+        }
+        (Method::Post, "predict_batch") => {
+            let body: PredictBatchBody = read_json(request)?;
+            let features_vec: Vec<Vec<Feature>> =
+                body.examples.into_iter().map(|e| e.features).collect();
+            let model = shared.model.read();
+            known_model(&model, model_name)?;
+            let ranked = model.try_predict_top_k_batch(model_name, &features_vec, usize::MAX)?;
+            let predictions: Vec<Value> = ranked.into_iter().map(prediction).collect();
+            Ok(json!({ "predictions": predictions }))
+        }
+        (Method::Post, "train") | (Method::Post, "untrain") => {
+            let body: TrainBody = read_json(request)?;
+            let examples: Vec<(String, Vec<Feature>)> = body
+                .examples
+                .into_iter()
+                .map(|e| (e.label, e.features))
+                .collect();
+            let done = if action == "train" {
+                shared.model.try_train(model_name, &examples)?;
+                "trained"
+            } else {
+                shared.model.try_untrain(model_name, &examples)?;
+                "untrained"
+            };
+            shared.changed.store(true, Ordering::SeqCst);
+            Ok(json!({ done: examples.len() }))
+        }
+        (Method::Get, "classes") => {
+            let model = shared.model.read();
+            known_model(&model, model_name)?;
+            let classes: Vec<Value> = model
+                .classes(model_name)
+                .into_iter()
+                .map(|(class, count)| json!({ "class": class, "count": count }))
+                .collect();
+            Ok(json!({ "classes": classes }))
+        }
+        (Method::Get, "stats") => {
+            let model = shared.model.read();
+            known_model(&model, model_name)?;
+            let classes = model.classes(model_name);
+            let examples: f64 = classes.iter().map(|(_, count)| count).sum();
+            let vocabulary: Map<String, Value> = model
+                .vocabulary_sizes(model_name)
+                .into_iter()
+                .map(|(feature_name, size)| (feature_name, size.into()))
+                .collect();
+            Ok(json!({
+                "classes": classes.len(),
+                "examples": examples,
+                "vocabulary": vocabulary,
+            }))
+        }
+        (_, "predict") | (_, "predict_batch") | (_, "train") | (_, "untrain") => {
+            Err(HttpError::new(405, format!("{} needs POST", path)))
+        }
+        (_, "classes") | (_, "stats") => Err(HttpError::new(405, format!("{} needs GET", path))),
+        _ => Err(HttpError::new(404, format!("no such path {}", path))),
+    }
+}
+
+fn read_json<B: serde::de::DeserializeOwned>(
+    request: &mut Request,
+) -> std::result::Result<B, HttpError> {
+    serde_json::from_reader(request.as_reader())
+        .map_err(|e| HttpError::new(400, format!("invalid request body: {}", e)))
+}
+
+fn known_model<T: ModelStore + Sync>(
+    model: &Model<T>,
+    model_name: &str,
+) -> std::result::Result<(), HttpError> {
+    if model.classes(model_name).is_empty() {
+        return Err(HttpError::new(404, format!("no model '{}'", model_name)));
+    }
+    Ok(())
+}
+
+fn prediction(ranked: Vec<(String, f64)>) -> Value {
+    let label = ranked.first().map(|(class, _)| class.to_owned());
+    let probabilities: Map<String, Value> = ranked
+        .into_iter()
+        .map(|(class, p)| (class, p.into()))
+        .collect();
+    json!({ "label": label, "probabilities": probabilities })
+}
@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+
+use crate::{
+    as_feature_refs, AsFeatureRef, Feature, FeatureRef, Model, ModelStore, Prediction, Result,
+    TrainingExample,
+};
+
+/// A model to train and predict with from many threads at once, e.g. in a service that keeps
+/// learning from feedback while serving predictions.
+///
+/// It keeps two copies of the model. Predictions read the published copy and never wait for
+/// training. Training tokenizes its examples without holding anything. It then adds their
+/// counts to the other copy and publishes that copy, so a prediction sees either none or all
+/// of the examples of a call. Calls from different threads are all counted, adding their
+/// counts one call after another.
+///
+/// The copy published before lacks the counts of the last call, they are added to it by the
+/// next call. If that copy is still read then, e.g. by a prediction or a model from `read`,
+/// the next call copies the published model instead of waiting. So the model takes twice the
+/// memory of a `Model`, and three times for as long as an old copy is read while training.
+///
+/// ```
+/// use rust_nb::{Feature, FeatureType, Model, SharedModel};
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let shared = Arc::new(SharedModel::new(Model::new()));
+/// let feature = |value: &str| Feature {
+///     feature_type: FeatureType::Text,
+///     name: "title".to_owned(),
+///     value: value.to_owned(),
+/// };
+///
+/// let trainers: Vec<_> = ["spam", "ham"]
+///     .iter()
+///     .map(|class| {
+///         let shared = Arc::clone(&shared);
+///         thread::spawn(move || {
+///             shared.train("model", &[(class.to_string(), vec![feature(class)])]);
+///         })
+///     })
+///     .collect();
+/// for trainer in trainers {
+///     trainer.join().unwrap();
+/// }
+///
+/// let result = shared.predict("model", &[feature("spam")]);
+/// assert!(result["spam"] > result["ham"]);
+/// ```
+pub struct SharedModel<T: ModelStore + Sync> {
+    // the copy predictions read, replaced by the standby copy when training
+    active: RwLock<Arc<Model<T>>>,
+    // the copy training changes
+    standby: Mutex<Standby<T>>,
+}
+
+struct Standby<T: ModelStore + Sync> {
+    model: Arc<Model<T>>,
+    lag: Lag,
+}
+
+// What the standby copy lacks of the published one.
+enum Lag {
+    Nothing,
+    Training(String, Vec<TrainingExample<'static>>),
+    // e.g. after an update or a panic while changing it, the published model is copied then
+    Unknown,
+}
+
+impl<T: ModelStore + Sync + Clone> SharedModel<T> {
+    pub fn new(model: Model<T>) -> Self {
+        SharedModel {
+            active: RwLock::new(Arc::new(model.clone())),
+            standby: Mutex::new(Standby {
+                model: Arc::new(model),
+                lag: Lag::Nothing,
+            }),
+        }
+    }
+
+    /// Change the model, e.g. merge another model into it. `f` is called once, the copy
+    /// published before is replaced by a copy of the changed model on the next change. If `f`
+    /// panics the model is left as it was.
+    pub fn update<F: FnOnce(&mut Model<T>)>(&self, f: F) {
+        self.change(|model| {
+            f(model);
+            Ok(Lag::Unknown)
+        })
+        .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn into_inner(self) -> Model<T> {
+        let active = self
+            .active
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        Arc::try_unwrap(active).unwrap_or_else(|active| Model::clone(&active))
+    }
+
+    // Change the standby copy with `f` and publish it. `f` returns what the copy published
+    // before lacks then. If `f` fails it has to leave the model unchanged, nothing is published.
+    fn change<F: FnOnce(&mut Model<T>) -> Result<Lag>>(&self, f: F) -> Result<()> {
+        // a panic while changing the standby copy leaves its lag unknown, so it is copied again
+        let mut standby = self.standby.lock().unwrap_or_else(PoisonError::into_inner);
+        let lag = match f(self.catch_up(&mut standby)) {
+            Ok(lag) => lag,
+            Err(e) => {
+                standby.lag = Lag::Nothing;
+                return Err(e);
+            }
+        };
+        mem::swap(&mut *self.active.write().unwrap(), &mut standby.model);
+        standby.lag = lag;
+        Ok(())
+    }
+
+    // The standby copy made the same as the published one, by adding the training it lacks or
+    // by copying the published one if it is still read or its lag is unknown.
+    fn catch_up<'a>(&self, standby: &'a mut Standby<T>) -> &'a mut Model<T> {
+        let lag = mem::replace(&mut standby.lag, Lag::Unknown);
+        let copy = match (Arc::get_mut(&mut standby.model), lag) {
+            (Some(_), Lag::Nothing) => false,
+            (Some(model), Lag::Training(model_name, examples)) => {
+                model.apply_training(&model_name, &examples);
+                false
+            }
+            _ => true,
+        };
+        if copy {
+            let active = self.read();
+            standby.model = Arc::new(Model::clone(&active));
+        }
+        Arc::get_mut(&mut standby.model)
+            .unwrap_or_else(|| unreachable!("the standby copy is shared"))
+    }
+
+    // Add prepared examples to the model, only if it knows their classes for `check_classes`.
+    fn apply_training(
+        &self,
+        model_name: &str,
+        examples: Vec<TrainingExample>,
+        check_classes: bool,
+    ) -> Result<()> {
+        // kept to be added to the copy published before, by the next change
+        let examples: Vec<_> = examples
+            .into_iter()
+            .map(TrainingExample::into_owned)
+            .collect();
+        self.change(|model| {
+            if check_classes {
+                model.check_classes(model_name, &examples)?;
+            }
+            model.apply_training(model_name, &examples);
+            Ok(Lag::Training(model_name.to_owned(), examples))
+        })
+    }
+
+    /// Train the model, panics where `try_train` would return an error.
+    pub fn train(&self, model_name: &str, class_feature_pairs: &[(String, Vec<Feature>)]) {
+        self.try_train(model_name, class_feature_pairs)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// As `Model::try_train`.
+    pub fn try_train(
+        &self,
+        model_name: &str,
+        class_feature_pairs: &[(String, Vec<Feature>)],
+    ) -> Result<()> {
+        self.train_examples(model_name, class_feature_pairs, 1.0)
+    }
+
+    /// As `train`, with examples borrowing their class and features, panics where
+    /// `try_train_ref` would return an error.
+    pub fn train_ref(&self, model_name: &str, class_feature_pairs: &[(&str, Vec<FeatureRef>)]) {
+        self.try_train_ref(model_name, class_feature_pairs)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// As `Model::try_train_ref`.
+    pub fn try_train_ref(
+        &self,
+        model_name: &str,
+        class_feature_pairs: &[(&str, Vec<FeatureRef>)],
+    ) -> Result<()> {
+        self.train_examples(model_name, class_feature_pairs, 1.0)
+    }
+
+    /// Train the model with a weight for each example, panics where `try_train_weighted`
+    /// would return an error.
+    pub fn train_weighted(
+        &self,
+        model_name: &str,
+        class_weight_feature_tuples: &[(String, f64, Vec<Feature>)],
+    ) {
+        self.try_train_weighted(model_name, class_weight_feature_tuples)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// As `Model::try_train_weighted`.
+    pub fn try_train_weighted(
+        &self,
+        model_name: &str,
+        class_weight_feature_tuples: &[(String, f64, Vec<Feature>)],
+    ) -> Result<()> {
+        let examples = self
+            .read()
+            .prepare_weighted_training(model_name, class_weight_feature_tuples)?;
+        self.apply_training(model_name, examples, false)
+    }
+
+    /// Remove previously trained examples from the model again, panics where `try_untrain`
+    /// would return an error.
+    pub fn untrain(&self, model_name: &str, class_feature_pairs: &[(String, Vec<Feature>)]) {
+        self.try_untrain(model_name, class_feature_pairs)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// As `Model::try_untrain`.
+    pub fn try_untrain(
+        &self,
+        model_name: &str,
+        class_feature_pairs: &[(String, Vec<Feature>)],
+    ) -> Result<()> {
+        self.train_examples(model_name, class_feature_pairs, -1.0)
+    }
+
+    // Train the examples with `weight`, untraining them for a negative weight.
+    fn train_examples<C: AsRef<str>, F: AsFeatureRef>(
+        &self,
+        model_name: &str,
+        class_feature_pairs: &[(C, Vec<F>)],
+        weight: f64,
+    ) -> Result<()> {
+        let examples = self.read().prepare_training(
+            model_name,
+            class_feature_pairs
+                .iter()
+                .map(|(class, features)| (class.as_ref(), weight, as_feature_refs(features))),
+        )?;
+        self.apply_training(model_name, examples, weight < 0.0)
+    }
+}
+
+impl<T: ModelStore + Sync> SharedModel<T> {
+    /// The model as it is now, e.g. to predict several times from the same counts. Holding it
+    /// never blocks training, also not on the same thread, but the next training copies the
+    /// model if it is still held then.
+    pub fn read(&self) -> Arc<Model<T>> {
+        Arc::clone(&self.active.read().unwrap())
+    }
+
+    /// Predict the probability of each class, panics where `try_predict` would return an error.
+    pub fn predict(&self, model_name: &str, features: &[Feature]) -> HashMap<String, f64> {
+        self.read().predict(model_name, features)
+    }
+
+    pub fn try_predict(
+        &self,
+        model_name: &str,
+        features: &[Feature],
+    ) -> Result<HashMap<String, f64>> {
+        self.read().try_predict(model_name, features)
+    }
+
+    /// As `predict`, with features borrowing their name and value.
+    pub fn predict_ref(&self, model_name: &str, features: &[FeatureRef]) -> HashMap<String, f64> {
+        self.read().predict_ref(model_name, features)
+    }
+
+    pub fn try_predict_ref(
+        &self,
+        model_name: &str,
+        features: &[FeatureRef],
+    ) -> Result<HashMap<String, f64>> {
+        self.read().try_predict_ref(model_name, features)
+    }
+
+    /// Predict all examples from the same counts.
+    pub fn predict_batch(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<Feature>],
+    ) -> Vec<HashMap<String, f64>> {
+        self.read().predict_batch(model_name, features_vec)
+    }
+
+    pub fn try_predict_batch(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<Feature>],
+    ) -> Result<Vec<HashMap<String, f64>>> {
+        self.read().try_predict_batch(model_name, features_vec)
+    }
+
+    /// As `predict_batch`, with features borrowing their name and value.
+    pub fn predict_batch_ref(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<FeatureRef>],
+    ) -> Vec<HashMap<String, f64>> {
+        self.read().predict_batch_ref(model_name, features_vec)
+    }
+
+    pub fn try_predict_batch_ref(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<FeatureRef>],
+    ) -> Result<Vec<HashMap<String, f64>>> {
+        self.read().try_predict_batch_ref(model_name, features_vec)
+    }
+
+    pub fn predict_batch_scores(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<Feature>],
+    ) -> Vec<Prediction> {
+        self.read().predict_batch_scores(model_name, features_vec)
+    }
+
+    pub fn try_predict_batch_scores(
+        &self,
+        model_name: &str,
+        features_vec: &[Vec<Feature>],
+    ) -> Result<Vec<Prediction>> {
+        self.read()
+            .try_predict_batch_scores(model_name, features_vec)
+    }
+}
@@ -0,0 +1,359 @@
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::sync::Arc;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Splits the value of a `Text` feature into the words that are counted.
+pub trait Tokenizer: Send + Sync {
+    fn tokenize(&self, text: &str) -> Vec<String>;
+
+    /// What is saved with a model to build this tokenizer again when the model is loaded.
+    fn spec(&self) -> TokenizerSpec;
+}
+
+/// The saved form of a tokenizer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TokenizerSpec {
+    Regex(#[serde(with = "serde_regex")] Regex),
+    UnicodeWord,
+    Whitespace,
+
+    /// A custom tokenizer can not be saved, only its name is. After loading the model
+    /// give it the tokenizer again, otherwise using it gives an `Error::MissingTokenizer`.
+    Custom(String),
+}
+
+/// Replaces every match of the regex with a space, lowercases, then splits on whitespace.
+/// The default regex `[^a-zA-Z]+` keeps only English letters, this is the default tokenizer.
+#[derive(Debug, Clone)]
+pub struct RegexTokenizer {
+    regex: Regex,
+}
+
+impl RegexTokenizer {
+    pub fn new(regex: Regex) -> Self {
+        RegexTokenizer { regex }
+    }
+}
+
+impl Default for RegexTokenizer {
+    fn default() -> Self {
+        RegexTokenizer::new(Regex::new(r"[^a-zA-Z]+").unwrap())
+    }
+}
+
+impl Tokenizer for RegexTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        clean_text(text, &self.regex)
+            .split_whitespace()
+            .map(|w| w.to_owned())
+            .collect()
+    }
+
+    fn spec(&self) -> TokenizerSpec {
+        TokenizerSpec::Regex(self.regex.clone())
+    }
+}
+
+/// Splits on the word boundaries of Unicode (UAX #29) and lowercases, so words of
+/// any language are kept, e.g. "Grüße aus Köln" gives "grüße", "aus" and "köln".
+#[derive(Debug, Clone, Default)]
+pub struct UnicodeWordTokenizer;
+
+impl Tokenizer for UnicodeWordTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        text.unicode_words().map(|w| w.to_lowercase()).collect()
+    }
+
+    fn spec(&self) -> TokenizerSpec {
+        TokenizerSpec::UnicodeWord
+    }
+}
+
+/// Splits on whitespace only, the words are kept as they are.
+#[derive(Debug, Clone, Default)]
+pub struct WhitespaceTokenizer;
+
+impl Tokenizer for WhitespaceTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        text.split_whitespace().map(|w| w.to_owned()).collect()
+    }
+
+    fn spec(&self) -> TokenizerSpec {
+        TokenizerSpec::Whitespace
+    }
+}
+
+/// A tokenizer made from a closure. The name is what is saved with a model,
+/// see `TokenizerSpec::Custom`.
+pub struct FnTokenizer<F: Fn(&str) -> Vec<String> + Send + Sync> {
+    name: String,
+    f: F,
+}
+
+impl<F: Fn(&str) -> Vec<String> + Send + Sync> FnTokenizer<F> {
+    pub fn new(name: &str, f: F) -> Self {
+        FnTokenizer {
+            name: name.to_owned(),
+            f,
+        }
+    }
+}
+
+impl<F: Fn(&str) -> Vec<String> + Send + Sync> Tokenizer for FnTokenizer<F> {
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        (self.f)(text)
+    }
+
+    fn spec(&self) -> TokenizerSpec {
+        TokenizerSpec::Custom(self.name.to_owned())
+    }
+}
+
+/// N-grams of a `Text` feature, counted as words of the feature so they share its vocabulary.
+/// Word n-grams are the words next to each other joined by a space, e.g. "new york".
+/// Character n-grams are taken within each word and start with the control character
+/// `CHAR_GRAM_MARK`, e.g. "\u{1}yor". The mark is taken out of the words of a feature with
+/// n-grams, so a word never looks like a character n-gram, whatever its tokenizer gives.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NGrams {
+    /// Smallest and largest number of words, `Some((1, 1))` is single words only.
+    pub words: Option<(usize, usize)>,
+
+    /// Smallest and largest number of characters.
+    pub chars: Option<(usize, usize)>,
+}
+
+/// The first character of a character n-gram, see `NGrams`.
+pub const CHAR_GRAM_MARK: char = '\u{1}';
+
+impl Default for NGrams {
+    fn default() -> Self {
+        NGrams {
+            words: Some((1, 1)),
+            chars: None,
+        }
+    }
+}
+
+impl NGrams {
+    /// Word n-grams from `min` to `max` words, e.g. `NGrams::words(1, 3)`.
+    pub fn words(min: usize, max: usize) -> Self {
+        NGrams {
+            words: Some((min, max)),
+            chars: None,
+        }
+    }
+
+    /// Character n-grams from `min` to `max` characters, without any word n-grams.
+    pub fn chars(min: usize, max: usize) -> Self {
+        NGrams {
+            words: None,
+            chars: Some((min, max)),
+        }
+    }
+
+    /// Character n-grams from `min` to `max` characters in addition.
+    pub fn with_chars(mut self, min: usize, max: usize) -> Self {
+        self.chars = Some((min, max));
+        self
+    }
+
+    pub(crate) fn apply(&self, words: &[String]) -> Vec<String> {
+        let unmarked: Vec<String>;
+        let words = if words.iter().any(|w| w.contains(CHAR_GRAM_MARK)) {
+            unmarked = words
+                .iter()
+                .map(|w| w.replace(CHAR_GRAM_MARK, ""))
+                .filter(|w| !w.is_empty())
+                .collect();
+            &unmarked[..]
+        } else {
+            words
+        };
+
+        let mut grams = vec![];
+
+        if let Some((min, max)) = self.words {
+            for n in min.max(1)..=max {
+                grams.extend(words.windows(n).map(|w| w.join(" ")));
+            }
+        }
+
+        if let Some((min, max)) = self.chars {
+            for word in words {
+                let chars: Vec<char> = word.chars().collect();
+                for n in min.max(1)..=max {
+                    grams.extend(
+                        chars
+                            .windows(n)
+                            .map(|c| std::iter::once(&CHAR_GRAM_MARK).chain(c).collect()),
+                    );
+                }
+            }
+        }
+
+        grams
+    }
+}
+
+// A tokenizer as the model holds it, a custom one is missing after the model is loaded
+// until it is given again.
+#[derive(Clone)]
+pub(crate) enum TokenizerSlot {
+    Ready(Arc<dyn Tokenizer>),
+    Missing(String),
+}
+
+impl TokenizerSlot {
+    pub(crate) fn new<K: Tokenizer + 'static>(tokenizer: K) -> Self {
+        TokenizerSlot::Ready(Arc::new(tokenizer))
+    }
+
+    /// The tokenizer, or an error if it is a custom one that has not been given again.
+    pub(crate) fn get(&self, feature_name: &str) -> crate::Result<&dyn Tokenizer> {
+        match self {
+            TokenizerSlot::Ready(tokenizer) => Ok(tokenizer.as_ref()),
+            TokenizerSlot::Missing(name) => Err(crate::Error::MissingTokenizer {
+                feature_name: feature_name.to_owned(),
+                tokenizer_name: name.to_owned(),
+            }),
+        }
+    }
+
+    fn spec(&self) -> TokenizerSpec {
+        match self {
+            TokenizerSlot::Ready(tokenizer) => tokenizer.spec(),
+            TokenizerSlot::Missing(name) => TokenizerSpec::Custom(name.to_owned()),
+        }
+    }
+}
+
+impl From<TokenizerSpec> for TokenizerSlot {
+    fn from(spec: TokenizerSpec) -> Self {
+        match spec {
+            TokenizerSpec::Regex(regex) => TokenizerSlot::new(RegexTokenizer::new(regex)),
+            TokenizerSpec::UnicodeWord => TokenizerSlot::new(UnicodeWordTokenizer),
+            TokenizerSpec::Whitespace => TokenizerSlot::new(WhitespaceTokenizer),
+            TokenizerSpec::Custom(name) => TokenizerSlot::Missing(name),
+        }
+    }
+}
+
+impl Serialize for TokenizerSlot {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.spec().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TokenizerSlot {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        TokenizerSpec::deserialize(deserializer).map(TokenizerSlot::from)
+    }
+}
+
+fn clean_text(text: &str, regex: &Regex) -> String {
+    let text = regex.replace_all(text, " ");
+    text.trim().to_lowercase()
+}
+
+#[test]
+fn clean_text_works() {
+    let text = "This is &/some weird TEXT";
+
+    let cleaned_text = clean_text(text, &Regex::new(r"[^a-zA-Z]+").unwrap());
+    assert_eq!("this is some weird text", cleaned_text);
+}
+
+#[test]
+fn tokenizers_work() {
+    let text = "Grüße aus  Köln, (again)";
+
+    assert_eq!(
+        vec!["gr", "e", "aus", "k", "ln", "again"],
+        RegexTokenizer::default().tokenize(text)
+    );
+    assert_eq!(
+        vec!["a", "b", "c"],
+        RegexTokenizer::new(Regex::new(r",").unwrap()).tokenize("A,,B, C")
+    );
+    assert_eq!(
+        vec!["grüße", "aus", "köln", "again"],
+        UnicodeWordTokenizer.tokenize(text)
+    );
+    assert_eq!(
+        vec!["Grüße", "aus", "Köln,", "(again)"],
+        WhitespaceTokenizer.tokenize(text)
+    );
+    assert_eq!(
+        vec!["Grüße aus  Köln", " (again)"],
+        FnTokenizer::new("comma", |t: &str| t
+            .split(',')
+            .map(|w| w.to_owned())
+            .collect())
+        .tokenize(text)
+    );
+}
+
+#[test]
+fn ngrams_work() {
+    let words: Vec<String> = vec!["new".to_owned(), "york".to_owned(), "city".to_owned()];
+
+    assert_eq!(words, NGrams::default().apply(&words));
+    assert_eq!(
+        vec![
+            "new",
+            "york",
+            "city",
+            "new york",
+            "york city",
+            "new york city"
+        ],
+        NGrams::words(1, 3).apply(&words)
+    );
+    assert_eq!(
+        vec!["new york", "york city"],
+        NGrams::words(2, 2).apply(&words)
+    );
+    assert_eq!(
+        vec![
+            "\u{1}new",
+            "\u{1}yor",
+            "\u{1}ork",
+            "\u{1}york",
+            "\u{1}cit",
+            "\u{1}ity",
+            "\u{1}city"
+        ],
+        NGrams::chars(3, 4).apply(&words)
+    );
+    assert_eq!(
+        vec!["york", "\u{1}yo", "\u{1}or", "\u{1}rk"],
+        NGrams::words(1, 1).with_chars(2, 2).apply(&words[1..2])
+    );
+
+    // a word can not pass for a character n-gram
+    let words = vec!["\u{1}ity".to_owned(), "\u{1}".to_owned()];
+    assert_eq!(
+        vec!["ity", "\u{1}ity"],
+        NGrams::words(1, 1).with_chars(3, 3).apply(&words)
+    );
+}
+
+#[test]
+fn tokenizer_slot_serde_works() {
+    let slot = TokenizerSlot::new(FnTokenizer::new("mine", |_: &str| vec![]));
+    let json = serde_json::to_string(&slot).unwrap();
+    assert_eq!(r#"{"Custom":"mine"}"#, json);
+    match serde_json::from_str::<TokenizerSlot>(&json).unwrap() {
+        TokenizerSlot::Missing(name) => assert_eq!("mine", name),
+        _ => panic!("a custom tokenizer is missing after loading"),
+    }
+
+    let json = serde_json::to_string(&TokenizerSlot::new(RegexTokenizer::default())).unwrap();
+    assert_eq!(r#"{"Regex":"[^a-zA-Z]+"}"#, json);
+    match serde_json::from_str::<TokenizerSlot>(&json).unwrap() {
+        TokenizerSlot::Ready(tokenizer) => assert_eq!(vec!["ok"], tokenizer.tokenize("ok?")),
+        _ => panic!("a regex tokenizer is ready after loading"),
+    }
+}
@@ -1,6 +1,15 @@
 #[cfg(test)]
 mod rust_nb {
-    use rust_nb::{Feature, FeatureType, Model};
+    use rust_nb::search::{self, Grid};
+    use rust_nb::{
+        eval, Error, Feature, FeatureRef, FeatureType, FnTokenizer, InvalidValuePolicy,
+        JsonlWriter, Model, ModelHashMapStore, NGrams, PriorCounting, Scoring, SharedModel,
+        UnicodeWordTokenizer,
+    };
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn model_works_simple_case() {
@@ -150,4 +159,1330 @@ mod rust_nb {
         assert!((0.8326445413521572 - *result.get("eur").unwrap()).abs() < 1e-6);
     }
 
+    #[test]
+    fn model_save_and_load_works() {
+        let mut model = Model::new().with_pseudo_count(0.5).with_prior_factor(0.8);
+
+        let input_train = vec![
+            (
+                "happy".to_owned(),
+                vec![Feature {
+                    feature_type: FeatureType::Text,
+                    name: "my_words".to_owned(),
+                    value: "The weather is so good".to_owned(),
+                }],
+            ),
+            (
+                "sad".to_owned(),
+                vec![Feature {
+                    feature_type: FeatureType::Text,
+                    name: "my_words".to_owned(),
+                    value: "that food tastes so bad".to_owned(),
+                }],
+            ),
+        ];
+        model.train("test_model", &input_train);
+
+        let input_test = vec![Feature {
+            feature_type: FeatureType::Text,
+            name: "my_words".to_owned(),
+            value: "thinking about the weather ...".to_owned(),
+        }];
+        let expected = model.predict("test_model", &input_test);
+
+        let path = std::env::temp_dir().join("rust_nb_model_save_and_load_works.json");
+        model.save(&path).unwrap();
+        let loaded = Model::<ModelHashMapStore>::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(expected, loaded.predict("test_model", &input_test));
+
+        // files written before the versioned envelope existed are still loaded
+        let bare = serde_json::to_vec(&model).unwrap();
+        let loaded = Model::<ModelHashMapStore>::load_from_reader(&bare[..]).unwrap();
+        assert_eq!(expected, loaded.predict("test_model", &input_test));
+        // and flagged as counting priors the old way
+        assert_eq!(PriorCounting::PerFeature, loaded.prior_counting());
+
+        // as are models deserialized with serde directly, without the prior counting
+        let mut bare = serde_json::to_value(&model).unwrap();
+        bare.as_object_mut().unwrap().remove("prior_counting");
+        let loaded: Model<ModelHashMapStore> = serde_json::from_value(bare).unwrap();
+        assert_eq!(PriorCounting::PerFeature, loaded.prior_counting());
+    }
+
+    #[test]
+    fn model_counts_priors_per_example() {
+        let mut model = Model::new();
+        assert_eq!(PriorCounting::PerExample, model.prior_counting());
+
+        let input_train = vec![
+            (
+                "spam".to_owned(),
+                vec![
+                    Feature {
+                        feature_type: FeatureType::Category,
+                        name: "domain".to_owned(),
+                        value: "evil.com".to_owned(),
+                    },
+                    Feature {
+                        feature_type: FeatureType::Text,
+                        name: "body".to_owned(),
+                        value: "money money".to_owned(),
+                    },
+                    Feature {
+                        feature_type: FeatureType::Gaussian,
+                        name: "n_words".to_owned(),
+                        value: "2".to_owned(),
+                    },
+                ],
+            ),
+            (
+                "not spam".to_owned(),
+                vec![Feature {
+                    feature_type: FeatureType::Category,
+                    name: "domain".to_owned(),
+                    value: "gmail.com".to_owned(),
+                }],
+            ),
+        ];
+        model.train("test_model", &input_train);
+
+        // without any features the prediction is the priors, one example of each class
+        let result = model.predict("test_model", &[]);
+        assert_eq!(0.5, *result.get("spam").unwrap());
+        assert_eq!(0.5, *result.get("not spam").unwrap());
+    }
+
+    #[test]
+    fn model_invalid_value_policy_works() {
+        let input_train = vec![
+            (
+                "young".to_owned(),
+                vec![Feature {
+                    feature_type: FeatureType::Gaussian,
+                    name: "age".to_owned(),
+                    value: "20".to_owned(),
+                }],
+            ),
+            (
+                "old".to_owned(),
+                vec![Feature {
+                    feature_type: FeatureType::Gaussian,
+                    name: "age".to_owned(),
+                    value: "unknown".to_owned(),
+                }],
+            ),
+        ];
+
+        let mut model = Model::new().with_invalid_value_policy(InvalidValuePolicy::Fail);
+        match model.try_train("test_model", &input_train) {
+            Err(Error::InvalidValue {
+                model_name,
+                feature_name,
+                value,
+            }) => {
+                assert_eq!("test_model", model_name);
+                assert_eq!("age", feature_name);
+                assert_eq!("unknown", value);
+            }
+            _ => panic!("expected an InvalidValue error"),
+        }
+        // nothing is trained when failing
+        assert!(model.try_predict("test_model", &[]).unwrap().is_empty());
+
+        let mut model = Model::new().with_invalid_value_policy(InvalidValuePolicy::Impute(80.0));
+        model.try_train("test_model", &input_train).unwrap();
+        let result = model
+            .try_predict(
+                "test_model",
+                &[Feature {
+                    feature_type: FeatureType::Gaussian,
+                    name: "age".to_owned(),
+                    value: "NaN".to_owned(),
+                }],
+            )
+            .unwrap();
+        assert!(*result.get("old").unwrap() > 0.9);
+
+        // skipping is the default
+        let mut model = Model::new();
+        model.try_train("test_model", &input_train).unwrap();
+        let result = model
+            .try_predict(
+                "test_model",
+                &[Feature {
+                    feature_type: FeatureType::Gaussian,
+                    name: "age".to_owned(),
+                    value: "".to_owned(),
+                }],
+            )
+            .unwrap();
+        assert_eq!(0.5, *result.get("old").unwrap());
+    }
+
+    #[test]
+    fn model_missing_stop_words_file_gives_error() {
+        match Model::new().try_with_stop_words_file("does/not/exist.txt") {
+            Err(Error::Io(_)) => {}
+            _ => panic!("expected an Io error"),
+        }
+    }
+
+    fn example(class: &str, words: &str, age: &str) -> (String, Vec<Feature>) {
+        (
+            class.to_owned(),
+            vec![
+                Feature {
+                    feature_type: FeatureType::Text,
+                    name: "my_words".to_owned(),
+                    value: words.to_owned(),
+                },
+                Feature {
+                    feature_type: FeatureType::GaussianStd,
+                    name: "age".to_owned(),
+                    value: age.to_owned(),
+                },
+            ],
+        )
+    }
+
+    #[test]
+    fn model_untrain_works() {
+        let input_train = vec![
+            example("happy", "good weather", "20"),
+            example("sad", "bad food", "40"),
+            example("happy", "terrible weather", "30"),
+        ];
+        let relabeled_train = vec![
+            example("happy", "good weather", "20"),
+            example("sad", "bad food", "40"),
+            example("sad", "terrible weather", "30"),
+        ];
+
+        let mut model = Model::new().with_default_gaussian_m2(4.0);
+        model.train("test_model", &input_train);
+        model.untrain("test_model", &input_train[2..]);
+
+        let mut expected = Model::new().with_default_gaussian_m2(4.0);
+        expected.train("test_model", &input_train[..2]);
+
+        let mut relabeled = Model::new().with_default_gaussian_m2(4.0);
+        relabeled.train("test_model", &input_train);
+        relabeled.untrain("test_model", &input_train[2..]);
+        relabeled.train("test_model", &relabeled_train[2..]);
+
+        let mut expected_relabeled = Model::new().with_default_gaussian_m2(4.0);
+        expected_relabeled.train("test_model", &relabeled_train);
+
+        for (words, age) in &[("terrible weather", "25"), ("good food", "35"), ("", "")] {
+            let input_test = example("", words, age).1;
+
+            let result = model.predict("test_model", &input_test);
+            for (class, p) in expected.predict("test_model", &input_test) {
+                assert!((p - result.get(&class).unwrap()).abs() < 1e-10);
+            }
+
+            let result = relabeled.predict("test_model", &input_test);
+            for (class, p) in expected_relabeled.predict("test_model", &input_test) {
+                assert!((p - result.get(&class).unwrap()).abs() < 1e-10);
+            }
+        }
+
+        // a class is gone when all its examples are removed
+        model.untrain("test_model", &input_train[1..2]);
+        let result = model.predict("test_model", &example("", "bad food", "40").1);
+        assert_eq!(1, result.len());
+        assert_eq!(1.0, *result.get("happy").unwrap());
+
+        match model.try_untrain("test_model", &input_train[1..2]) {
+            Err(Error::UnknownClass { class, .. }) => assert_eq!("sad", class),
+            _ => panic!("expected an UnknownClass error"),
+        }
+    }
+
+    #[test]
+    fn model_train_weighted_works() {
+        let mut model = Model::new();
+        model.train_weighted(
+            "test_model",
+            &[
+                ("happy".to_owned(), 2.0, example("", "good weather", "20").1),
+                ("sad".to_owned(), 0.5, example("", "bad food", "40").1),
+                ("sad".to_owned(), 0.5, example("", "bad food", "40").1),
+            ],
+        );
+
+        let mut expected = Model::new();
+        expected.train(
+            "test_model",
+            &[
+                example("happy", "good weather", "20"),
+                example("happy", "good weather", "20"),
+                example("sad", "bad food", "40"),
+            ],
+        );
+
+        for (words, age) in &[("good food", "35"), ("bad weather", "20"), ("", "")] {
+            let input_test = example("", words, age).1;
+            let result = model.predict("test_model", &input_test);
+            for (class, p) in expected.predict("test_model", &input_test) {
+                assert!((p - result.get(&class).unwrap()).abs() < 1e-10);
+            }
+        }
+
+        match model.try_train_weighted("test_model", &[("sad".to_owned(), -1.0, vec![])]) {
+            Err(Error::InvalidWeight { weight, .. }) => assert_eq!(-1.0, weight),
+            _ => panic!("expected an InvalidWeight error"),
+        }
+    }
+
+    #[test]
+    fn model_train_weighted_keeps_tiny_weights() {
+        let mut model = Model::new();
+        model.train_weighted(
+            "test_model",
+            &[
+                ("a".to_owned(), 1.0, example("", "good weather", "20").1),
+                ("b".to_owned(), 1e-10, example("", "bad food", "40").1),
+            ],
+        );
+        assert_eq!(
+            vec![("a".to_owned(), 1.0), ("b".to_owned(), 1e-10)],
+            model.classes("test_model")
+        );
+
+        let result = model.predict("test_model", &example("", "bad food", "40").1);
+        assert!(result["b"] > 0.0 && result["b"] < result["a"]);
+
+        model.untrain("test_model", &[example("a", "good weather", "20")]);
+        assert_eq!(vec![("b".to_owned(), 1e-10)], model.classes("test_model"));
+    }
+
+    #[test]
+    fn model_tokenizers_work() {
+        fn split_tags(text: &str) -> Vec<String> {
+            text.split(',').map(|t| t.trim().to_owned()).collect()
+        }
+
+        fn features(words: &str, tags: &str) -> Vec<Feature> {
+            vec![
+                Feature {
+                    feature_type: FeatureType::Text,
+                    name: "my_words".to_owned(),
+                    value: words.to_owned(),
+                },
+                Feature {
+                    feature_type: FeatureType::Text,
+                    name: "tags".to_owned(),
+                    value: tags.to_owned(),
+                },
+            ]
+        }
+
+        let mut model = Model::new()
+            .with_tokenizer(UnicodeWordTokenizer)
+            .with_feature_tokenizer("tags", FnTokenizer::new("comma", split_tags));
+
+        model.train(
+            "test_model",
+            &[
+                (
+                    "de".to_owned(),
+                    features("Grüße aus Köln", "hallo welt, köln"),
+                ),
+                (
+                    "sv".to_owned(),
+                    features("Hälsningar från Malmö", "hej, malmö"),
+                ),
+            ],
+        );
+
+        let input_test = features("Schöne Grüße", "");
+        let result = model.predict("test_model", &input_test);
+        assert!(*result.get("de").unwrap() > 0.6);
+
+        let input_test = features("", "hallo welt");
+        let result = model.predict("test_model", &input_test);
+        assert!(*result.get("de").unwrap() > 0.6);
+
+        // the custom tokenizer has to be given again after loading
+        let mut saved = vec![];
+        model.save_to_writer(&mut saved).unwrap();
+        let loaded = Model::<ModelHashMapStore>::load_from_reader(&saved[..]).unwrap();
+        match loaded.try_predict("test_model", &input_test) {
+            Err(Error::MissingTokenizer { tokenizer_name, .. }) => {
+                assert_eq!("comma", tokenizer_name)
+            }
+            _ => panic!("expected a MissingTokenizer error"),
+        }
+
+        let loaded = loaded.with_feature_tokenizer("tags", FnTokenizer::new("comma", split_tags));
+        assert_eq!(
+            model.predict("test_model", &input_test),
+            loaded.predict("test_model", &input_test)
+        );
+        let input_test = features("Schöne Grüße", "");
+        assert_eq!(
+            model.predict("test_model", &input_test),
+            loaded.predict("test_model", &input_test)
+        );
+    }
+
+    #[test]
+    fn model_ngrams_work() {
+        let input_train = vec![
+            example("spam", "you are a winner", ""),
+            example("ham", "the meeting is at noon", ""),
+            example("ham", "york is a city", ""),
+            example("spam", "new jersey is a state", ""),
+        ];
+
+        // an unknown word is shared by no class, but its character n-grams are
+        let mut model =
+            Model::new().with_feature_ngrams("my_words", NGrams::words(1, 1).with_chars(3, 5));
+        model.train("test_model", &input_train);
+        let result = model.predict("test_model", &example("", "winners", "").1);
+        assert!(*result.get("spam").unwrap() > 0.6);
+
+        // "new" and "york" are seen in different classes, only the pair "new york" is ham
+        let mut model = Model::new().with_feature_ngrams("my_words", NGrams::words(1, 2));
+        model.train("test_model", &input_train);
+        model.train("test_model", &[example("ham", "new york", "")]);
+        let result = model.predict("test_model", &example("", "new york", "").1);
+        let mut unigram_model = Model::new();
+        unigram_model.train("test_model", &input_train);
+        unigram_model.train("test_model", &[example("ham", "new york", "")]);
+        let unigram_result = unigram_model.predict("test_model", &example("", "new york", "").1);
+        assert!(*result.get("ham").unwrap() > *unigram_result.get("ham").unwrap());
+    }
+
+    #[test]
+    fn model_bernoulli_works() {
+        let doc = |class: &str, words: &str| {
+            (
+                class.to_owned(),
+                vec![Feature {
+                    feature_type: FeatureType::Bernoulli,
+                    name: "keywords".to_owned(),
+                    value: words.to_owned(),
+                }],
+            )
+        };
+
+        let mut model = Model::new();
+        model.train(
+            "test_model",
+            &[
+                doc("spam", "a a"),
+                doc("spam", "a"),
+                doc("ham", "b"),
+                doc("ham", ""),
+            ],
+        );
+
+        // spam: p(a) = 3/4, p(b) = 1/4, ham: p(a) = 1/4, p(b) = 2/4,
+        // the absent "a" counts too: p(b, not a | ham) = 3/8, p(b, not a | spam) = 1/16
+        let result = model.predict("test_model", &doc("", "b").1);
+        assert!((result.get("ham").unwrap() - 6.0 / 7.0).abs() < 1e-9);
+
+        // training again updates the cached sums over the vocabulary
+        model.train("test_model", &[doc("spam", "b")]);
+        let result = model.predict("test_model", &doc("", "b").1);
+        assert!(*result.get("ham").unwrap() < 6.0 / 7.0);
+
+        model.untrain("test_model", &[doc("spam", "b")]);
+        let result = model.predict("test_model", &doc("", "b").1);
+        assert!((result.get("ham").unwrap() - 6.0 / 7.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn model_complement_scoring_works() {
+        let mut model = Model::new();
+        model.train(
+            "test_model",
+            &[example("spam", "x x y", ""), example("ham", "y", "")],
+        );
+
+        // spam is scored by the counts of ham: -log p(x | not spam) = -log(1/3),
+        // ham by the counts of spam: -log p(x | not ham) = -log(3/5)
+        let model = model.with_scoring(Scoring::Complement {
+            normalize_weights: false,
+        });
+        let result = model.predict("test_model", &example("", "x", "").1);
+        assert!((result.get("spam").unwrap() - 9.0 / 14.0).abs() < 1e-9);
+
+        // weights divided by the sum over the vocabulary: log(1/3) + log(2/3) for spam,
+        // log(3/5) + log(2/5) for ham
+        let model = model.with_scoring(Scoring::Complement {
+            normalize_weights: true,
+        });
+        let result = model.predict("test_model", &example("", "x", "").1);
+        let spam = (3.0f64.ln() / 4.5f64.ln()).exp();
+        let ham = ((5.0f64 / 3.0).ln() / (25.0f64 / 6.0).ln()).exp();
+        assert!((result.get("spam").unwrap() - spam / (spam + ham)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn model_predict_scores_works() {
+        let mut model = Model::new();
+        model.train(
+            "test_model",
+            &[example("spam", "x x y", ""), example("ham", "y", "")],
+        );
+
+        let features = example("", "x", "").1;
+        let prediction = model.predict_scores("test_model", &features);
+
+        // log(p(c_n)) + log((count(x, c_n) + 1) / (count(c_n) + |V|))
+        let spam = 0.5f64.ln() + (3.0f64 / 5.0).ln();
+        let ham = 0.5f64.ln() + (1.0f64 / 3.0).ln();
+        assert!((prediction.log_scores.get("spam").unwrap() - spam).abs() < 1e-9);
+        assert!((prediction.log_scores.get("ham").unwrap() - ham).abs() < 1e-9);
+        assert!((prediction.log_evidence - (spam.exp() + ham.exp()).ln()).abs() < 1e-9);
+        assert_eq!(
+            model.predict("test_model", &features),
+            prediction.probabilities
+        );
+
+        // a model without classes gives an empty prediction for each example
+        let predictions = model.predict_batch_scores("no_model", &[features.clone(), features]);
+        assert_eq!(2, predictions.len());
+        assert!(predictions.iter().all(|p| p.probabilities.is_empty()));
+    }
+
+    #[test]
+    fn model_freeze_predicts_the_same() {
+        let feature = |feature_type: FeatureType, name: &str, value: &str| Feature {
+            feature_type,
+            name: name.to_owned(),
+            value: value.to_owned(),
+        };
+        let doc = |class: &str, text: &str, color: &str, tags: &str, age: &str, size: &str| {
+            (
+                class.to_owned(),
+                vec![
+                    feature(FeatureType::Text, "text", text),
+                    feature(FeatureType::Category, "color", color),
+                    feature(FeatureType::Bernoulli, "tags", tags),
+                    feature(FeatureType::GaussianStd, "age", age),
+                    feature(FeatureType::Gaussian, "size", size),
+                ],
+            )
+        };
+
+        let mut model = Model::new();
+        model.train(
+            "test_model",
+            &[
+                doc(
+                    "spam",
+                    "buy cheap pills now",
+                    "red",
+                    "offer urgent",
+                    "25",
+                    "3.5",
+                ),
+                doc("spam", "cheap cheap watches", "red", "offer", "31", "2.0"),
+                doc("ham", "meeting at noon", "blue", "work", "45", "10.0"),
+                doc(
+                    "ham",
+                    "lunch at noon tomorrow",
+                    "green",
+                    "work family",
+                    "52",
+                    "8.5",
+                ),
+                doc("eggs", "green eggs and ham", "green", "", "7", "1.0"),
+            ],
+        );
+
+        let examples: Vec<Vec<Feature>> = vec![
+            doc("", "cheap lunch now now", "red", "offer work", "30", "4.0").1,
+            doc("", "unknown words only", "purple", "nothing", "x", "100").1,
+            doc("", "green eggs", "green", "", "8", "1.5").1,
+            vec![feature(FeatureType::Text, "other", "cheap")],
+            vec![],
+        ];
+
+        // features given with another type than they were trained with
+        let swapped: Vec<Vec<Feature>> = vec![
+            vec![feature(FeatureType::Text, "tags", "offer work work")],
+            vec![feature(FeatureType::Category, "tags", "urgent")],
+            vec![feature(FeatureType::Bernoulli, "text", "cheap noon")],
+            vec![feature(FeatureType::Bernoulli, "color", "red")],
+            vec![feature(FeatureType::Text, "age", "25")],
+        ];
+
+        for scoring in &[
+            Scoring::Multinomial,
+            Scoring::Complement {
+                normalize_weights: false,
+            },
+            Scoring::Complement {
+                normalize_weights: true,
+            },
+        ] {
+            model = model.with_scoring(*scoring);
+            let frozen = model.freeze();
+            assert_eq!(
+                frozen.predict_batch_scores("test_model", &examples),
+                model.predict_batch_scores("test_model", &examples)
+            );
+            for features in &examples {
+                assert_eq!(
+                    frozen.predict("test_model", features),
+                    model.predict("test_model", features)
+                );
+            }
+            for features in &swapped {
+                let expected = model.try_predict_scores("test_model", features);
+                let frozen = frozen.try_predict_scores("test_model", features);
+                assert_eq!(expected.is_ok(), frozen.is_ok());
+                assert_eq!(expected.ok(), frozen.ok());
+            }
+        }
+
+        // the frozen model stays as it was when the model is trained further
+        let frozen = model.freeze();
+        let before = frozen.predict("test_model", &examples[0]);
+        model.train(
+            "test_model",
+            &[doc("ham", "cheap lunch", "red", "offer", "30", "4.0")],
+        );
+        assert_eq!(frozen.predict("test_model", &examples[0]), before);
+        assert_ne!(model.predict("test_model", &examples[0]), before);
+
+        assert!(frozen.predict("no_model", &examples[0]).is_empty());
+        assert_eq!(
+            examples.len(),
+            frozen.predict_batch("no_model", &examples).len()
+        );
+    }
+
+    #[test]
+    fn model_train_par_and_merge_work() {
+        let data: Vec<(String, Vec<Feature>)> = (0..200)
+            .map(|i| {
+                let (class, words) = match i % 3 {
+                    0 => ("spam", "cheap pills money"),
+                    1 => ("ham", "meeting at noon"),
+                    _ => ("eggs", "green eggs and ham"),
+                };
+                let mut example = example(class, words, &(i % 37 + i % 3 * 20).to_string());
+                example.1.push(Feature {
+                    feature_type: FeatureType::Gaussian,
+                    name: "size".to_owned(),
+                    value: (i % 11 * (i % 3 + 1)).to_string(),
+                });
+                example
+            })
+            .collect();
+        let test: Vec<Vec<Feature>> = data.iter().take(6).map(|(_, f)| f.clone()).collect();
+
+        let assert_same = |a: &Model<ModelHashMapStore>, b: &Model<ModelHashMapStore>| {
+            assert_eq!(a.classes("test_model"), b.classes("test_model"));
+            assert_eq!(
+                a.vocabulary_sizes("test_model"),
+                b.vocabulary_sizes("test_model")
+            );
+            for (a, b) in a
+                .predict_batch_scores("test_model", &test)
+                .iter()
+                .zip(b.predict_batch_scores("test_model", &test))
+            {
+                for (class, score) in &a.log_scores {
+                    assert!((score - b.log_scores[class]).abs() < 1e-9);
+                }
+            }
+        };
+
+        // complement scoring also takes the counts of words in all classes
+        for scoring in &[
+            Scoring::Multinomial,
+            Scoring::Complement {
+                normalize_weights: true,
+            },
+        ] {
+            let new_model = || Model::new().with_scoring(*scoring);
+
+            let mut model = new_model();
+            model.train("test_model", &data);
+
+            let mut par_model = new_model();
+            par_model.train_par("test_model", &data);
+            assert_same(&model, &par_model);
+
+            // halves trained apart, e.g. on separate machines, and saved
+            let (first, second) = data.split_at(77);
+            let mut first_model = new_model();
+            first_model.train("test_model", first);
+            let mut second_model = new_model();
+            second_model.train("test_model", second);
+            let second_model: Model<ModelHashMapStore> =
+                serde_json::from_str(&serde_json::to_string(&second_model).unwrap()).unwrap();
+
+            first_model.merge(&second_model);
+            assert_same(&model, &first_model);
+        }
+    }
+
+    #[test]
+    fn model_train_iter_works() {
+        let data = vec![
+            example("spam", "win money now", "20"),
+            example("spam", "money money", "25"),
+            example("ham", "meeting at noon", "40"),
+        ];
+        let features = example("", "money at noon", "30").1;
+
+        let mut model = Model::new();
+        model.train("test_model", &data);
+
+        let mut iter_model = Model::new();
+        iter_model.train_iter("test_model", data.clone());
+        assert_eq!(
+            iter_model.predict("test_model", &features),
+            model.predict("test_model", &features)
+        );
+
+        // rows held in a buffer of our own, split into borrowed features
+        let rows = "spam,win money now,20\nspam,money money,25\nham,meeting at noon,40";
+        let examples: Vec<(&str, Vec<FeatureRef>)> = rows
+            .lines()
+            .map(|row| {
+                let mut columns = row.split(',');
+                let class = columns.next().unwrap();
+                let features = vec![
+                    FeatureRef {
+                        feature_type: FeatureType::Text,
+                        name: "my_words",
+                        value: columns.next().unwrap(),
+                    },
+                    FeatureRef {
+                        feature_type: FeatureType::GaussianStd,
+                        name: "age",
+                        value: columns.next().unwrap(),
+                    },
+                ];
+                (class, features)
+            })
+            .collect();
+        let mut ref_model = Model::new();
+        ref_model.train_iter_ref(
+            "test_model",
+            examples.iter().map(|(class, f)| (*class, f.as_slice())),
+        );
+        assert_eq!(
+            ref_model.predict("test_model", &features),
+            model.predict("test_model", &features)
+        );
+
+        // the examples before a failing one stay trained
+        let mut model = Model::new().with_invalid_value_policy(InvalidValuePolicy::Fail);
+        let result = model.try_train_iter(
+            "test_model",
+            vec![example("spam", "money", "20"), example("ham", "noon", "x")],
+        );
+        assert!(matches!(result, Err(Error::InvalidValue { .. })));
+        assert_eq!(model.classes("test_model"), vec![("spam".to_owned(), 1.0)]);
+    }
+
+    #[test]
+    fn model_feature_ref_works() {
+        let data = vec![
+            example("spam", "win money now", "20"),
+            example("ham", "meeting at noon", "40"),
+        ];
+        let mut model = Model::new();
+        model.train("test_model", &data);
+
+        // the same examples borrowed, with borrowed class names too
+        let borrowed: Vec<(&str, Vec<FeatureRef>)> = data
+            .iter()
+            .map(|(class, features)| (class.as_str(), features.iter().map(From::from).collect()))
+            .collect();
+        let mut ref_model = Model::new();
+        ref_model.train_ref("test_model", &borrowed);
+
+        let text = String::from("money at noon");
+        let features = [
+            FeatureRef {
+                feature_type: FeatureType::Text,
+                name: "my_words",
+                value: &text,
+            },
+            FeatureRef {
+                feature_type: FeatureType::GaussianStd,
+                name: "age",
+                value: "30",
+            },
+        ];
+        let owned = example("", &text, "30").1;
+        assert_eq!(
+            ref_model.predict_ref("test_model", &features),
+            model.predict("test_model", &owned)
+        );
+        assert_eq!(
+            model.predict_batch_ref("test_model", &[features.to_vec()]),
+            model.predict_batch("test_model", &[owned])
+        );
+    }
+
+    #[test]
+    fn shared_model_works() {
+        let shared = Arc::new(SharedModel::new(Model::new()));
+        let threads = 8;
+        let rounds = 20;
+
+        // every call trains one ham and one spam, so they always have the same count
+        let trainers: Vec<_> = (0..threads as u8)
+            .map(|t| {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || {
+                    for i in 0..rounds as u8 {
+                        let letter = |n: u8| (b'a' + n) as char;
+                        let words = format!("thread{} round{} money", letter(t), letter(i));
+                        shared.train(
+                            "test_model",
+                            &[
+                                example("ham", "hello friend", "30"),
+                                example("spam", &words, "50"),
+                            ],
+                        );
+                    }
+                })
+            })
+            .collect();
+
+        let reader = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                for _ in 0..rounds {
+                    let model = shared.read();
+                    let classes = model.classes("test_model");
+                    if let [(_, ham), (_, spam)] = classes.as_slice() {
+                        assert_eq!(ham, spam);
+                    }
+                    model.predict_batch("test_model", &[example("", "money", "40").1]);
+                }
+            })
+        };
+
+        for trainer in trainers {
+            trainer.join().unwrap();
+        }
+        reader.join().unwrap();
+
+        let model = shared.read();
+        let examples = (threads * rounds) as f64;
+        assert_eq!(
+            model.classes("test_model"),
+            vec![("ham".to_owned(), examples), ("spam".to_owned(), examples)]
+        );
+        assert_eq!(
+            model.top_words("test_model", "my_words", "spam", 1),
+            vec![("money".to_owned(), examples)]
+        );
+        // hello, friend, money, a word of each thread and a word of each round
+        assert_eq!(
+            model.vocabulary_sizes("test_model"),
+            vec![("my_words".to_owned(), 3 + 8 + 20)]
+        );
+        drop(model);
+
+        shared.untrain("test_model", &[example("ham", "hello friend", "30")]);
+        assert_eq!(shared.read().classes("test_model")[0].1, examples - 1.0);
+    }
+
+    #[test]
+    fn shared_model_predicts_while_training() {
+        let shared = Arc::new(SharedModel::new(Model::new()));
+        shared.train("test_model", &[example("spam", "money", "50")]);
+
+        let training = Arc::new(AtomicBool::new(false));
+        let predicted = Arc::new(AtomicBool::new(false));
+        let trainer = {
+            let (shared, training, predicted) = (
+                Arc::clone(&shared),
+                Arc::clone(&training),
+                Arc::clone(&predicted),
+            );
+            thread::spawn(move || {
+                shared.update(|model| {
+                    model.train("test_model", &[example("ham", "hello", "30")]);
+                    // hold the model until a prediction is made meanwhile
+                    training.store(true, Ordering::SeqCst);
+                    let start = Instant::now();
+                    while !predicted.load(Ordering::SeqCst) {
+                        assert!(start.elapsed() < Duration::from_secs(10));
+                        thread::yield_now();
+                    }
+                });
+            })
+        };
+
+        while !training.load(Ordering::SeqCst) {
+            thread::yield_now();
+        }
+        // the model as it was before the training
+        assert_eq!(
+            vec![("spam".to_owned(), 1.0)],
+            shared.read().classes("test_model")
+        );
+        let result = shared.predict("test_model", &example("", "money", "50").1);
+        assert_eq!(1.0, result["spam"]);
+        predicted.store(true, Ordering::SeqCst);
+
+        trainer.join().unwrap();
+        assert_eq!(2, shared.read().classes("test_model").len());
+        assert_eq!(
+            2,
+            Arc::try_unwrap(shared)
+                .ok()
+                .unwrap()
+                .into_inner()
+                .classes("test_model")
+                .len()
+        );
+    }
+
+    #[test]
+    fn shared_model_trains_while_read_on_the_same_thread() {
+        let shared = SharedModel::new(Model::new());
+        let before = shared.read();
+        for class in &["spam", "ham", "eggs"] {
+            shared.train("test_model", &[example(class, "money", "50")]);
+        }
+        let after = shared.read();
+        shared.untrain("test_model", &[example("eggs", "money", "50")]);
+
+        assert!(before.classes("test_model").is_empty());
+        assert_eq!(3, after.classes("test_model").len());
+        let classes = vec![("ham".to_owned(), 1.0), ("spam".to_owned(), 1.0)];
+        assert_eq!(classes, shared.read().classes("test_model"));
+        assert_eq!(classes, shared.into_inner().classes("test_model"));
+    }
+
+    #[test]
+    fn shared_model_is_unchanged_by_a_panicking_update() {
+        let shared = SharedModel::new(Model::new());
+        shared.train("test_model", &[example("spam", "money", "50")]);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            shared.update(|model| {
+                model.train("test_model", &[example("ham", "hello", "30")]);
+                panic!("update failed");
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(
+            vec![("spam".to_owned(), 1.0)],
+            shared.read().classes("test_model")
+        );
+
+        // both copies are trained the same way afterwards
+        shared.train("test_model", &[example("ham", "hello", "30")]);
+        shared.train("test_model", &[example("spam", "money", "50")]);
+        let classes = vec![("ham".to_owned(), 1.0), ("spam".to_owned(), 2.0)];
+        assert_eq!(classes, shared.read().classes("test_model"));
+        shared.update(|_| {});
+        assert_eq!(classes, shared.into_inner().classes("test_model"));
+    }
+
+    #[test]
+    fn model_explain_works() {
+        let mut model = Model::new();
+        model.train(
+            "test_model",
+            &[example("spam", "x x y", ""), example("ham", "y", "")],
+        );
+
+        let features = example("", "y x x unknown", "").1;
+        let explanation = model.explain("test_model", &features);
+        let prediction = model.predict_scores("test_model", &features);
+
+        // classes in their order, ham before spam
+        assert_eq!("ham", explanation[0].class);
+        let spam = &explanation[1];
+        assert_eq!("spam", spam.class);
+        assert!((spam.prior - 0.5f64.ln()).abs() < 1e-9);
+        assert!((spam.log_score - prediction.log_scores.get("spam").unwrap()).abs() < 1e-9);
+
+        let words = &spam.features[0];
+        assert_eq!("my_words", words.name);
+        assert_eq!(vec!["unknown".to_owned()], words.ignored_tokens);
+        // y contributes log(2/5), more than x twice with log(3/5)
+        assert_eq!(2, words.tokens.len());
+        assert_eq!("y", words.tokens[0].token);
+        assert_eq!("x", words.tokens[1].token);
+        assert_eq!(2.0, words.tokens[1].count);
+        assert_eq!(2.0, words.tokens[1].count_in_class);
+        assert!((words.tokens[1].log_prob - (3.0f64 / 5.0).ln()).abs() < 1e-9);
+        assert!(
+            (words.contribution - (3.0f64 / 5.0).ln() * 2.0 - (2.0f64 / 5.0).ln()).abs() < 1e-9
+        );
+
+        // the age feature has no value, it does not contribute
+        assert_eq!(0.0, spam.features[1].contribution);
+    }
+
+    #[test]
+    fn model_predict_top_k_and_label_work() {
+        let mut model = Model::new();
+        model.train(
+            "test_model",
+            &[
+                example("spam", "x", ""),
+                example("eggs", "y", ""),
+                example("ham", "y", ""),
+            ],
+        );
+
+        // eggs and ham are the same, the tie goes to the first class in order
+        let features = example("", "y", "").1;
+        let top = model.predict_top_k("test_model", &features, 2);
+        assert_eq!(2, top.len());
+        assert_eq!("eggs", top[0].0);
+        assert_eq!("ham", top[1].0);
+        assert_eq!(top[0].1, top[1].1);
+        assert_eq!(3, model.predict_top_k("test_model", &features, 5).len());
+        assert_eq!(
+            "eggs",
+            model.predict_label("test_model", &features).unwrap().0
+        );
+
+        let labels =
+            model.predict_label_batch("test_model", &[example("", "x", "").1, features.clone()]);
+        assert_eq!("spam", labels[0].as_ref().unwrap().0);
+        assert_eq!("eggs", labels[1].as_ref().unwrap().0);
+        assert_eq!(
+            vec![top],
+            model.predict_top_k_batch("test_model", &[features], 2)
+        );
+
+        assert_eq!(None, Model::new().predict_label("test_model", &[]));
+    }
+
+    #[test]
+    fn eval_works() {
+        let mut model = Model::new();
+        model.train(
+            "test_model",
+            &[example("spam", "x", ""), example("ham", "y", "")],
+        );
+
+        let test_data = vec![
+            example("spam", "x", ""),
+            example("spam", "y", ""),
+            example("ham", "y", ""),
+            example("ham", "y", ""),
+        ];
+        let evaluation = eval::evaluate(&model, "test_model", &test_data);
+
+        // p(spam | x) = p(ham | y) = 2/3
+        assert_eq!(vec!["ham", "spam"], evaluation.classes);
+        assert_eq!(vec![vec![2, 0], vec![1, 1]], evaluation.confusion_matrix);
+        assert_eq!(0.75, evaluation.accuracy);
+        assert_eq!(0.75, evaluation.micro_average.f1);
+        let ham = &evaluation.per_class[0];
+        assert!((ham.metrics.precision - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(1.0, ham.metrics.recall);
+        assert_eq!(2, ham.support);
+        let spam = &evaluation.per_class[1].metrics;
+        assert!((evaluation.macro_average.recall - (1.0 + spam.recall) / 2.0).abs() < 1e-9);
+
+        let log_loss = -(3.0 * (2.0f64 / 3.0).ln() + (1.0f64 / 3.0).ln()) / 4.0;
+        assert!((evaluation.log_loss - log_loss).abs() < 1e-9);
+        let brier = (3.0 * 2.0 / 9.0 + 2.0 * 4.0 / 9.0) / 4.0;
+        assert!((evaluation.brier_score - brier).abs() < 1e-9);
+
+        let report = evaluation.to_string();
+        assert!(report.contains("accuracy"));
+        assert!(serde_json::to_string(&evaluation).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "at least as many examples as folds")]
+    fn eval_cross_validate_needs_an_example_per_fold() {
+        let data = vec![
+            example("spam", "money", ""),
+            example("ham", "meeting", ""),
+            example("ham", "lunch", ""),
+        ];
+        eval::cross_validate(Model::new, &data, 5, 42);
+    }
+
+    #[test]
+    fn eval_cross_validate_works() {
+        let mut data = vec![];
+        for i in 0..10 {
+            data.push(example("spam", &format!("x{} money", i), ""));
+            data.push(example("ham", &format!("y{} meeting", i), ""));
+        }
+        data.push(example("ham", "money meeting", ""));
+
+        let cv = eval::cross_validate(Model::new, &data, 5, 42);
+        assert_eq!(5, cv.folds.len());
+        assert_eq!(
+            data.len(),
+            cv.folds.iter().map(|fold| fold.count).sum::<usize>()
+        );
+        // every fold has both classes
+        assert!(cv.folds.iter().all(|fold| fold.per_class[1].support == 2));
+        assert!(cv.accuracy > 0.9);
+        assert_eq!(cv, eval::cross_validate(Model::new, &data, 5, 42));
+
+        let (train_data, test_data) = eval::train_test_split(data.clone(), 0.2, 7);
+        assert_eq!(17, train_data.len());
+        assert_eq!(
+            2,
+            test_data
+                .iter()
+                .filter(|(label, _)| label == "spam")
+                .count()
+        );
+        assert_eq!(
+            2,
+            test_data.iter().filter(|(label, _)| label == "ham").count()
+        );
+        assert_eq!(test_data, eval::train_test_split(data, 0.2, 7).1,);
+    }
+
+    #[test]
+    fn search_works() {
+        let mut data = vec![];
+        for i in 0..10 {
+            data.push(example("spam", &format!("x{} money", i), ""));
+            data.push(example("ham", &format!("y{} meeting", i), ""));
+        }
+
+        let grid = Grid::new()
+            .with_pseudo_count(&[0.1, 1.0, 100.0])
+            .with_prior_factor(&[1.0, 2.0]);
+        let result = search::search("test_model", Model::new, &grid.settings(), &data, 4, 42);
+
+        assert_eq!(6, result.ranking.len());
+        assert!(result
+            .ranking
+            .windows(2)
+            .all(|w| w[0].cross_validation.accuracy >= w[1].cross_validation.accuracy));
+
+        let mut best_model = result.best_settings().apply(Model::new());
+        best_model.train("test_model", &data);
+        let features = example("", "money", "").1;
+        assert_eq!(
+            best_model.predict("test_model", &features),
+            result.best_model.predict("test_model", &features)
+        );
+        assert!(result.to_string().contains("pseudo_count="));
+    }
+
+    #[test]
+    fn cli_works() {
+        use std::process::Command;
+
+        let dir = std::env::temp_dir().join(format!("rust_nb_cli_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data = dir.join("data.jsonl");
+        let model_file = dir.join("model.json");
+
+        let mut writer = JsonlWriter::from_path(&data).unwrap();
+        for (label, features) in &[
+            example("spam", "win money now", ""),
+            example("spam", "money money", ""),
+            example("ham", "meeting at noon", ""),
+        ] {
+            writer.write(Some(label), features).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let rust_nb = |args: &[&str]| {
+            let output = Command::new(env!("CARGO_BIN_EXE_rust-nb"))
+                .args(args)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{:?}", output);
+            String::from_utf8(output.stdout).unwrap()
+        };
+        let data = data.to_str().unwrap();
+        let model_file = model_file.to_str().unwrap();
+
+        rust_nb(&["train", "--input", data, "--model", model_file]);
+        let predictions = rust_nb(&["predict", "--input", data, "--model", model_file]);
+        let first: serde_json::Value =
+            serde_json::from_str(predictions.lines().next().unwrap()).unwrap();
+        assert_eq!("spam", first["label"]);
+        assert!(rust_nb(&["eval", "--input", data, "--model", model_file]).contains("accuracy"));
+        let inspected = rust_nb(&["inspect", "--model", model_file, "--top", "1"]);
+        assert!(inspected.contains("spam: money (3)"));
+
+        let other_file = dir.join("other.json");
+        let other_file = other_file.to_str().unwrap();
+        rust_nb(&["train", "--input", data, "--model", other_file]);
+        rust_nb(&["merge", "--model", model_file, "--from", other_file]);
+        let inspected = rust_nb(&["inspect", "--model", model_file, "--top", "1"]);
+        assert!(inspected.contains("spam: money (6)"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // a request to a server over localhost, answering the status and the json body
+    #[cfg(feature = "server")]
+    fn http(
+        addr: std::net::SocketAddr,
+        method: &str,
+        path: &str,
+        body: &str,
+    ) -> (u16, serde_json::Value) {
+        use std::io::{Read, Write};
+
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let (head, body) = response.split_at(response.find("\r\n\r\n").unwrap());
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body.trim()).unwrap())
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn server_works() {
+        use rust_nb::server::Server;
+        use std::time::Duration;
+
+        let snapshot =
+            std::env::temp_dir().join(format!("rust_nb_server_{}.json", std::process::id()));
+        let server = Server::bind("127.0.0.1:0", Model::new())
+            .unwrap()
+            .with_snapshots(&snapshot, Duration::from_millis(50));
+        let addr = server.addr();
+        let model = server.model();
+        std::thread::spawn(move || server.run());
+
+        let (status, _) = http(addr, "GET", "/models/spam/classes", "");
+        assert_eq!(404, status);
+
+        let train = r#"{"examples": [
+            {"label": "spam", "features": [{"type": "Text", "name": "title", "value": "win money"}]},
+            {"label": "ham", "features": [{"type": "Text", "name": "title", "value": "lunch at noon"}]},
+            {"label": "ham", "features": [{"type": "Text", "name": "title", "value": "meeting"}]}
+        ]}"#;
+        let (status, body) = http(addr, "POST", "/models/spam/train", train);
+        assert_eq!(200, status);
+        assert_eq!(3, body["trained"]);
+
+        let predict = r#"{"features": [{"type": "Text", "name": "title", "value": "money"}]}"#;
+        let (status, body) = http(addr, "POST", "/models/spam/predict", predict);
+        assert_eq!(200, status);
+        assert_eq!("spam", body["label"]);
+        assert!(body["probabilities"]["spam"].as_f64().unwrap() > 0.5);
+
+        let batch = format!(
+            r#"{{"examples": [{}, {}]}}"#,
+            predict, r#"{"features": []}"#
+        );
+        let (status, body) = http(addr, "POST", "/models/spam/predict_batch", &batch);
+        assert_eq!(200, status);
+        assert_eq!("spam", body["predictions"][0]["label"]);
+        assert_eq!("ham", body["predictions"][1]["label"]);
+
+        let (_, body) = http(addr, "GET", "/models/spam/stats", "");
+        assert_eq!(2, body["classes"]);
+        assert_eq!(3.0, body["examples"]);
+        assert_eq!(6, body["vocabulary"]["title"]);
+
+        // predictions running alongside training see the model before or after each batch
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                std::thread::spawn(move || {
+                    for _ in 0..10 {
+                        if i % 2 == 0 {
+                            let (status, _) = http(addr, "POST", "/models/spam/predict", predict);
+                            assert_eq!(200, status);
+                        } else {
+                            let (status, _) = http(addr, "POST", "/models/spam/train", train);
+                            assert_eq!(200, status);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(
+            vec![("ham".to_owned(), 42.0), ("spam".to_owned(), 21.0)],
+            model.read().classes("spam")
+        );
+
+        let (status, body) = http(addr, "POST", "/models/spam/untrain", train);
+        assert_eq!(200, status);
+        assert_eq!(3, body["untrained"]);
+        let (_, body) = http(addr, "GET", "/models/spam/classes", "");
+        assert_eq!(
+            serde_json::json!([{"class": "ham", "count": 40.0}, {"class": "spam", "count": 20.0}]),
+            body["classes"]
+        );
+
+        let (status, body) = http(
+            addr,
+            "POST",
+            "/models/spam/untrain",
+            r#"{"examples": [{"label": "eggs", "features": []}]}"#,
+        );
+        assert_eq!(400, status);
+        assert!(body["error"].as_str().unwrap().contains("eggs"));
+        assert_eq!(
+            400,
+            http(addr, "POST", "/models/spam/predict", "not json").0
+        );
+        assert_eq!(405, http(addr, "GET", "/models/spam/predict", "").0);
+        assert_eq!(404, http(addr, "GET", "/other", "").0);
+
+        // wait for a snapshot after the last training
+        std::thread::sleep(Duration::from_millis(300));
+        let saved = Model::<ModelHashMapStore>::load(&snapshot).unwrap();
+        assert_eq!(model.read().classes("spam"), saved.classes("spam"));
+        std::fs::remove_file(&snapshot).unwrap();
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn server_reports_failed_snapshots() {
+        use rust_nb::server::Server;
+        use std::sync::mpsc;
+        use std::time::Duration;
+
+        let (errors, failed) = mpsc::channel();
+        let snapshot = std::env::temp_dir()
+            .join(format!("rust_nb_no_such_dir_{}", std::process::id()))
+            .join("model.json");
+        let server = Server::bind("127.0.0.1:0", Model::new())
+            .unwrap()
+            .with_snapshots(&snapshot, Duration::from_millis(20))
+            .with_snapshot_errors(move |path, e| {
+                errors.send((path.to_owned(), e.to_string())).unwrap();
+            });
+        let addr = server.addr();
+        std::thread::spawn(move || server.run());
+
+        let train = r#"{"examples": [{"label": "spam", "features": []}]}"#;
+        assert_eq!(200, http(addr, "POST", "/models/spam/train", train).0);
+
+        // failed snapshots are tried again
+        for _ in 0..2 {
+            let (path, _) = failed.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(snapshot, path);
+        }
+    }
 }